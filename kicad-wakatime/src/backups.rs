@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use zip::ZipArchive;
+
+/// Extensions of the KiCAD documents that are compared between backups.
+pub const DOCUMENT_EXTENSIONS: &[&str] = &["kicad_sch", "kicad_pcb", "kicad_sym", "kicad_mod"];
+
+/// How a document differs between two backups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+  Added,
+  Modified,
+  Removed,
+}
+
+/// A document that differs between two backups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentChange {
+  /// Path of the document inside the backup .zip.
+  pub name: String,
+  pub change: Change,
+}
+
+impl DocumentChange {
+  /// Return the file name of the document, without the folders inside the .zip.
+  pub fn file_name(&self) -> &str {
+    Path::new(&self.name)
+      .file_name()
+      .and_then(|n| n.to_str())
+      .unwrap_or(&self.name)
+  }
+}
+
+/// Return whether a path inside a backup is a KiCAD document.
+pub fn is_document(name: &str) -> bool {
+  Path::new(name)
+    .extension()
+    .and_then(|e| e.to_str())
+    .is_some_and(|e| DOCUMENT_EXTENSIONS.contains(&e))
+}
+
+/// The backups in a backups folder, oldest first.
+/// The folder is read once; after that the index is kept up to date from file watcher events,
+/// so that a new backup does not mean sorting hundreds of files again.
+#[derive(Debug, Default)]
+pub struct BackupIndex {
+  backups: Vec<(SystemTime, PathBuf)>,
+}
+
+impl BackupIndex {
+  /// Index every backup in a backups folder.
+  pub fn load(backups_folder: &Path) -> Result<Self, anyhow::Error> {
+    let mut index = BackupIndex::default();
+    for entry in fs::read_dir(backups_folder)?.flatten() {
+      index.insert(entry.path());
+    }
+    Ok(index)
+  }
+  /// Add a backup to the index. Anything that is not a .zip file is ignored.
+  pub fn insert(&mut self, path: PathBuf) {
+    let Ok(metadata) = path.metadata() else { return };
+    // creation time is not available on every filesystem
+    let Ok(time) = metadata.created().or_else(|_| metadata.modified()) else { return };
+    self.insert_at(path, time);
+  }
+  /// Add a backup to the index with a known creation time.
+  pub fn insert_at(&mut self, path: PathBuf, time: SystemTime) {
+    if path.extension().is_none_or(|e| e != "zip") || self.contains(&path) {
+      return
+    }
+    let i = self.backups.partition_point(|(t, _)| *t <= time);
+    self.backups.insert(i, (time, path));
+  }
+  /// Remove a backup from the index.
+  pub fn remove(&mut self, path: &Path) {
+    self.backups.retain(|(_, p)| p != path);
+  }
+  pub fn contains(&self, path: &Path) -> bool {
+    self.backups.iter().any(|(_, p)| p == path)
+  }
+  pub fn len(&self) -> usize {
+    self.backups.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.backups.is_empty()
+  }
+  /// Return the backups and their creation times, oldest first.
+  pub fn backups(&self) -> &[(SystemTime, PathBuf)] {
+    &self.backups
+  }
+  /// Return the second newest and newest backups, if there are at least two.
+  pub fn newest_two(&self) -> Option<(&Path, &Path)> {
+    match self.backups.as_slice() {
+      [.., (_, second_newest), (_, newest)] => Some((second_newest, newest)),
+      _ => None,
+    }
+  }
+}
+
+/// What the central directory of a backup says about a file, without decompressing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EntryInfo {
+  index: usize,
+  crc32: u32,
+  size: u64,
+}
+
+/// List every file in a backup that `include` accepts, keyed by its path inside the .zip.
+fn zip_entries(
+  archive: &mut ZipArchive<BufReader<File>>,
+  include: fn(&str) -> bool,
+) -> Result<BTreeMap<String, EntryInfo>, anyhow::Error> {
+  let mut entries = BTreeMap::new();
+  for index in 0..archive.len() {
+    let file = archive.by_index_raw(index)?;
+    if !file.is_file() || !include(file.name()) {
+      continue
+    }
+    entries.insert(file.name().to_string(), EntryInfo {
+      index,
+      crc32: file.crc32(),
+      size: file.size(),
+    });
+  }
+  Ok(entries)
+}
+
+/// Decompress two documents side by side and compare them, without holding either in memory.
+fn contents_equal(
+  older: &mut ZipArchive<BufReader<File>>,
+  older_index: usize,
+  newer: &mut ZipArchive<BufReader<File>>,
+  newer_index: usize,
+) -> Result<bool, anyhow::Error> {
+  let mut older = older.by_index(older_index)?;
+  let mut newer = newer.by_index(newer_index)?;
+  let mut older_buf = [0u8; 64 * 1024];
+  let mut newer_buf = [0u8; 64 * 1024];
+  loop {
+    let n = older.read(&mut older_buf)?;
+    if n == 0 {
+      return Ok(newer.read(&mut newer_buf[..1])? == 0)
+    }
+    if newer.read_exact(&mut newer_buf[..n]).is_err() || older_buf[..n] != newer_buf[..n] {
+      return Ok(false)
+    }
+  }
+}
+
+/// Compare every KiCAD document in two backups.
+/// Documents are compared by their stored CRC32 and size;
+/// they are only decompressed if the archive is missing a CRC.
+pub fn diff_backups(older: &Path, newer: &Path) -> Result<Vec<DocumentChange>, anyhow::Error> {
+  diff_files(older, newer, is_document)
+}
+
+/// Return whether two backups hold exactly the same files, including project settings
+/// and library tables, so that the newer one can be deleted without losing anything.
+pub fn backups_identical(older: &Path, newer: &Path) -> Result<bool, anyhow::Error> {
+  Ok(diff_files(older, newer, |_| true)?.is_empty())
+}
+
+/// Compare the files in two backups that `include` accepts.
+fn diff_files(
+  older: &Path,
+  newer: &Path,
+  include: fn(&str) -> bool,
+) -> Result<Vec<DocumentChange>, anyhow::Error> {
+  let mut older_archive = ZipArchive::new(BufReader::new(File::open(older)?))?;
+  let mut newer_archive = ZipArchive::new(BufReader::new(File::open(newer)?))?;
+  let older_entries = zip_entries(&mut older_archive, include)?;
+  let newer_entries = zip_entries(&mut newer_archive, include)?;
+  let mut changes = vec![];
+  for (name, newer_entry) in &newer_entries {
+    let change = match older_entries.get(name) {
+      None => Change::Added,
+      Some(older_entry) if older_entry.size != newer_entry.size => Change::Modified,
+      Some(older_entry) if older_entry.crc32 != newer_entry.crc32 => Change::Modified,
+      // a CRC of 0 for a non-empty file means it was not recorded
+      Some(older_entry) if older_entry.crc32 == 0 && older_entry.size != 0 => {
+        if contents_equal(&mut older_archive, older_entry.index, &mut newer_archive, newer_entry.index)? {
+          continue
+        }
+        Change::Modified
+      },
+      Some(_) => continue,
+    };
+    changes.push(DocumentChange { name: name.clone(), change });
+  }
+  for name in older_entries.keys().filter(|name| !newer_entries.contains_key(*name)) {
+    changes.push(DocumentChange { name: name.clone(), change: Change::Removed });
+  }
+  Ok(changes)
+}
@@ -0,0 +1,78 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use ini::Ini;
+
+use crate::config::Config;
+use crate::paths;
+
+/// A copy of .wakatime.cfg with an API key or URL that is not in it
+/// (e.g. from `--api-key` or `WAKATIME_API_KEY`), for the WakaTime CLI to read with `--config`.
+/// This keeps the key out of the CLI's command line, where other users could see it.
+/// Only the current user can read the file, and it is removed when dropped.
+pub struct CliConfig {
+  pub path: PathBuf,
+  // the settings written to the file
+  api_key: Option<String>,
+  api_url: Option<String>,
+}
+
+impl CliConfig {
+  /// Write a private config with the API key and URL from `config`
+  /// and everything else from `wakatime_config`.
+  pub fn write(wakatime_config: &Ini, config: &Config) -> Result<Self, anyhow::Error> {
+    let mut ini = wakatime_config.clone();
+    // only the key and URL are written to .wakatime.cfg
+    config.to_ini(&mut ini, &mut Ini::new());
+    let mut contents = vec![];
+    ini.write_to(&mut contents)?;
+    let path = paths::state_dir().join(file_name(std::process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path)?;
+    // the mode only applies to new files, and one left behind may be readable by others
+    #[cfg(unix)]
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(&contents)?;
+    Ok(CliConfig {
+      path,
+      api_key: config.api_key.clone(),
+      api_url: config.api_url.clone(),
+    })
+  }
+  /// Return whether the file has the API key and URL from `config`.
+  pub fn is_up_to_date(&self, config: &Config) -> bool {
+    self.api_key == config.api_key && self.api_url == config.api_url
+  }
+}
+
+/// Remove the private configs left behind by kicad-wakatimes that did not exit cleanly,
+/// and return their paths. Only call this while holding the instance lock.
+pub fn remove_stale() -> Vec<PathBuf> {
+  let own = file_name(std::process::id());
+  let Ok(entries) = fs::read_dir(paths::state_dir()) else { return vec![]; };
+  entries
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|name| {
+      name != own && name.strip_prefix("wakatime-")
+        .and_then(|rest| rest.strip_suffix(".cfg"))
+        .is_some_and(|pid| pid.parse::<u32>().is_ok())
+    }))
+    .filter(|path| fs::remove_file(path).is_ok())
+    .collect()
+}
+
+fn file_name(pid: u32) -> String {
+  format!("wakatime-{pid}.cfg")
+}
+
+impl Drop for CliConfig {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.path);
+  }
+}
@@ -0,0 +1,628 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use anyhow::Context;
+use ini::Ini;
+use log::{info, warn, LevelFilter};
+use regex::Regex;
+use thiserror::Error;
+
+use crate::idle::DEFAULT_IDLE_TIMEOUT;
+use crate::pause::QuietHours;
+use crate::prune::PruneMode;
+
+/// Version of the .kicad-wakatime.cfg layout written by this version of kicad-wakatime.
+/// - 0: `projects_folder` (a folder) or a single `projects_file`, no version key
+/// - 1: any number of `projects_file` keys
+pub const CONFIG_VERSION: u32 = 1;
+
+const SETTINGS: Option<&str> = Some("settings");
+
+/// Settings from .wakatime.cfg (shared with other WakaTime plugins) and .kicad-wakatime.cfg.
+/// Keys that kicad-wakatime does not know about are left alone when saving.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+  // .wakatime.cfg
+  pub api_key: Option<String>,
+  pub api_url: Option<String>,
+  // .kicad-wakatime.cfg
+  pub projects_files: Vec<PathBuf>,
+  pub symbol_file: Option<PathBuf>,
+  pub footprint_folder: Option<PathBuf>,
+  pub prune_mode: PruneMode,
+  pub auto_track: bool,
+  // levels of the log file and of the log printed to stderr, if not the defaults
+  pub log_level: Option<LevelFilter>,
+  pub stderr_log_level: Option<LevelFilter>,
+  // seconds without activity after which heartbeats stop, 0 to never stop, if not the default
+  pub idle_timeout: Option<u64>,
+  // seconds between heartbeats while a document stays focused, 0 to turn off, if not the default
+  pub keep_alive_interval: Option<u64>,
+  // weekly times when no heartbeats are sent
+  pub quiet_hours: Vec<QuietHours>,
+}
+
+/// Time between heartbeats while a document stays focused, unless set in the settings.
+/// Heartbeats are never sent more often than every 2 minutes anyway.
+pub const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+/// The problems with a config, remembered until the config changes, since checking that
+/// the files in it exist is too slow to do on every repaint.
+#[derive(Default)]
+pub struct Validation(RefCell<Option<(Config, Vec<ConfigProblem>)>>);
+
+impl Validation {
+  pub fn problems(&self, config: &Config) -> Vec<ConfigProblem> {
+    let mut validated = self.0.borrow_mut();
+    match &*validated {
+      Some((validated_config, problems)) if validated_config == config => problems.clone(),
+      _ => {
+        let problems = config.validate();
+        *validated = Some((config.clone(), problems.clone()));
+        problems
+      },
+    }
+  }
+}
+
+/// Something wrong with a setting.
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum ConfigProblem {
+  #[error("API key is not set")]
+  MissingApiKey,
+  #[error("API key does not look like a WakaTime API key")]
+  MalformedApiKey,
+  #[error("API URL is not set")]
+  MissingApiUrl,
+  #[error("API URL {0:?} is not a valid http(s) URL")]
+  MalformedApiUrl(String),
+  #[error("No KiCAD projects are set")]
+  NoProjects,
+  #[error("Project {0:?} does not exist")]
+  MissingProject(PathBuf),
+  #[error("{0:?} is not a .kicad_pro file")]
+  NotAProject(PathBuf),
+  #[error("Symbol library {0:?} does not exist")]
+  MissingSymbolFile(PathBuf),
+  #[error("Footprint library folder {0:?} does not exist")]
+  MissingFootprintFolder(PathBuf),
+}
+
+impl Config {
+  /// Read the settings out of the two config files.
+  /// Bad values are skipped with a warning, so that one typo doesn't lose the other settings.
+  pub fn from_ini(wakatime: &Ini, kicad_wakatime: &Ini) -> Self {
+    let get = |ini: &Ini, key: &str| {
+      ini.get_from(SETTINGS, key)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+    };
+    let projects_files = match kicad_wakatime.section(SETTINGS) {
+      Some(settings) => settings.get_all("projects_file")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .collect(),
+      None => vec![],
+    };
+    let quiet_hours = match kicad_wakatime.section(SETTINGS) {
+      Some(settings) => settings.get_all("quiet_hours")
+        .filter(|value| !value.is_empty())
+        .filter_map(|value| parse_setting("quiet_hours", value, "like \"mon-fri 18:00-09:00\""))
+        .collect(),
+      None => vec![],
+    };
+    let log_level = |key: &str| -> Option<LevelFilter> {
+      get(kicad_wakatime, key)
+        .and_then(|level| parse_setting(key, &level, "off, error, warn, info, debug or trace"))
+    };
+    let seconds = |key: &str| -> Option<u64> {
+      get(kicad_wakatime, key)
+        .and_then(|seconds| parse_setting(key, &seconds, "a number of seconds"))
+    };
+    Config {
+      api_key: get(wakatime, "api_key"),
+      api_url: get(wakatime, "api_url"),
+      projects_files,
+      symbol_file: get(kicad_wakatime, "symbol_file").map(PathBuf::from),
+      footprint_folder: get(kicad_wakatime, "footprint_folder").map(PathBuf::from),
+      prune_mode: get(kicad_wakatime, "prune_backups")
+        .and_then(|prune_mode| parse_setting("prune_backups", &prune_mode, "off, dry-run or on"))
+        .unwrap_or_default(),
+      auto_track: get(kicad_wakatime, "auto_track_projects")
+        .and_then(|auto_track| parse_setting("auto_track_projects", &auto_track, "true or false"))
+        .unwrap_or_default(),
+      log_level: log_level("log_level"),
+      stderr_log_level: log_level("stderr_log_level"),
+      idle_timeout: seconds("idle_timeout"),
+      keep_alive_interval: seconds("keep_alive_interval"),
+      quiet_hours,
+    }
+  }
+  /// Write the settings into the two config files. Unset settings are removed rather than left empty.
+  pub fn to_ini(&self, wakatime: &mut Ini, kicad_wakatime: &mut Ini) {
+    fn set(ini: &mut Ini, key: &str, value: Option<String>) {
+      let settings = ini.entry(SETTINGS.map(String::from)).or_insert(Default::default());
+      match value {
+        Some(value) => settings.insert(key, value),
+        None => { settings.remove(key); },
+      }
+    }
+    let to_string = |path: &PathBuf| path.to_string_lossy().to_string();
+    set(wakatime, "api_key", self.api_key.clone());
+    set(wakatime, "api_url", self.api_url.clone());
+    let settings = kicad_wakatime.entry(SETTINGS.map(String::from)).or_insert(Default::default());
+    let _ = settings.remove_all("projects_file");
+    for projects_file in &self.projects_files {
+      settings.append("projects_file", to_string(projects_file));
+    }
+    let settings = kicad_wakatime.entry(SETTINGS.map(String::from)).or_insert(Default::default());
+    let _ = settings.remove_all("quiet_hours");
+    for quiet_hours in &self.quiet_hours {
+      settings.append("quiet_hours", quiet_hours.to_string());
+    }
+    set(kicad_wakatime, "config_version", Some(CONFIG_VERSION.to_string()));
+    set(kicad_wakatime, "symbol_file", self.symbol_file.as_ref().map(to_string));
+    set(kicad_wakatime, "footprint_folder", self.footprint_folder.as_ref().map(to_string));
+    set(kicad_wakatime, "prune_backups", Some(self.prune_mode.to_string()));
+    set(kicad_wakatime, "auto_track_projects", Some(self.auto_track.to_string()));
+    set(kicad_wakatime, "log_level", self.log_level.map(|l| l.to_string().to_lowercase()));
+    set(kicad_wakatime, "stderr_log_level", self.stderr_log_level.map(|l| l.to_string().to_lowercase()));
+    set(kicad_wakatime, "idle_timeout", self.idle_timeout.map(|t| t.to_string()));
+    set(kicad_wakatime, "keep_alive_interval", self.keep_alive_interval.map(|t| t.to_string()));
+  }
+  /// Load the settings from the two config files. Missing files are treated as empty.
+  /// Old .kicad-wakatime.cfg layouts are migrated in memory; they are written back on the next save.
+  pub fn load(
+    wakatime_cfg_path: &Path,
+    kicad_wakatime_cfg_path: &Path
+  ) -> Result<(Self, Ini, Ini), anyhow::Error> {
+    let wakatime = load_ini(wakatime_cfg_path)?;
+    let mut kicad_wakatime = load_ini(kicad_wakatime_cfg_path)?;
+    migrate(&mut kicad_wakatime);
+    let config = Config::from_ini(&wakatime, &kicad_wakatime);
+    Ok((config, wakatime, kicad_wakatime))
+  }
+  /// Write the settings changed compared to `base` to the config files, keeping the changes
+  /// made to the files since `base` was loaded, and the keys of other programs.
+  /// Return the settings as saved, and the config files.
+  pub fn save(
+    base: &Config,
+    config: &Config,
+    wakatime_cfg_path: &Path,
+    kicad_wakatime_cfg_path: &Path
+  ) -> Result<(Self, Ini, Ini), anyhow::Error> {
+    let (disk_config, mut wakatime, mut kicad_wakatime) = Config::load(wakatime_cfg_path, kicad_wakatime_cfg_path)?;
+    let config = Config::merge(base, config, &disk_config);
+    config.to_ini(&mut wakatime, &mut kicad_wakatime);
+    Ini::write_to_file(&wakatime, wakatime_cfg_path)?;
+    Ini::write_to_file(&kicad_wakatime, kicad_wakatime_cfg_path)?;
+    Ok((config, wakatime, kicad_wakatime))
+  }
+  /// Combine two sets of changes made to the same settings.
+  /// Settings that `ours` changed compared to `base` come from `ours`, the rest from `theirs`.
+  pub fn merge(base: &Config, ours: &Config, theirs: &Config) -> Config {
+    fn pick<T: Clone + PartialEq>(base: &T, ours: &T, theirs: &T) -> T {
+      if ours != base { ours.clone() } else { theirs.clone() }
+    }
+    Config {
+      api_key: pick(&base.api_key, &ours.api_key, &theirs.api_key),
+      api_url: pick(&base.api_url, &ours.api_url, &theirs.api_url),
+      projects_files: pick(&base.projects_files, &ours.projects_files, &theirs.projects_files),
+      symbol_file: pick(&base.symbol_file, &ours.symbol_file, &theirs.symbol_file),
+      footprint_folder: pick(&base.footprint_folder, &ours.footprint_folder, &theirs.footprint_folder),
+      prune_mode: pick(&base.prune_mode, &ours.prune_mode, &theirs.prune_mode),
+      auto_track: pick(&base.auto_track, &ours.auto_track, &theirs.auto_track),
+      log_level: pick(&base.log_level, &ours.log_level, &theirs.log_level),
+      stderr_log_level: pick(&base.stderr_log_level, &ours.stderr_log_level, &theirs.stderr_log_level),
+      idle_timeout: pick(&base.idle_timeout, &ours.idle_timeout, &theirs.idle_timeout),
+      keep_alive_interval: pick(&base.keep_alive_interval, &ours.keep_alive_interval, &theirs.keep_alive_interval),
+      quiet_hours: pick(&base.quiet_hours, &ours.quiet_hours, &theirs.quiet_hours),
+    }
+  }
+  /// Return how long the user can be inactive before heartbeats stop, or `None` if they never stop.
+  pub fn idle_timeout(&self) -> Option<Duration> {
+    match self.idle_timeout {
+      Some(0) => None,
+      Some(seconds) => Some(Duration::from_secs(seconds)),
+      None => Some(DEFAULT_IDLE_TIMEOUT),
+    }
+  }
+  /// Return how often a heartbeat is sent while a document stays focused, or `None` if they aren't.
+  pub fn keep_alive_interval(&self) -> Option<Duration> {
+    match self.keep_alive_interval {
+      Some(0) => None,
+      Some(seconds) => Some(Duration::from_secs(seconds)),
+      None => Some(DEFAULT_KEEP_ALIVE_INTERVAL),
+    }
+  }
+  /// Return everything that is wrong with the settings.
+  pub fn validate(&self) -> Vec<ConfigProblem> {
+    let mut problems = vec![];
+    match &self.api_key {
+      None => problems.push(ConfigProblem::MissingApiKey),
+      Some(api_key) if !is_api_key(api_key) => problems.push(ConfigProblem::MalformedApiKey),
+      Some(_) => {},
+    }
+    match &self.api_url {
+      None => problems.push(ConfigProblem::MissingApiUrl),
+      Some(api_url) if !is_api_url(api_url) => problems.push(ConfigProblem::MalformedApiUrl(api_url.clone())),
+      Some(_) => {},
+    }
+    if self.projects_files.is_empty() && !self.auto_track {
+      problems.push(ConfigProblem::NoProjects);
+    }
+    for projects_file in &self.projects_files {
+      if projects_file.extension().is_none_or(|e| e != "kicad_pro") {
+        problems.push(ConfigProblem::NotAProject(projects_file.clone()));
+      } else if !projects_file.is_file() {
+        problems.push(ConfigProblem::MissingProject(projects_file.clone()));
+      }
+    }
+    if let Some(symbol_file) = &self.symbol_file {
+      if !symbol_file.is_file() {
+        problems.push(ConfigProblem::MissingSymbolFile(symbol_file.clone()));
+      }
+    }
+    if let Some(footprint_folder) = &self.footprint_folder {
+      if !footprint_folder.is_dir() {
+        problems.push(ConfigProblem::MissingFootprintFolder(footprint_folder.clone()));
+      }
+    }
+    problems
+  }
+}
+
+/// Settings given as command line flags or environment variables.
+/// They take precedence over the config files, but are never written to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverrides {
+  pub api_key: Option<String>,
+  pub api_url: Option<String>,
+  pub projects_files: Vec<PathBuf>,
+  pub symbol_file: Option<PathBuf>,
+  pub footprint_folder: Option<PathBuf>,
+  pub prune_mode: Option<PruneMode>,
+  pub auto_track: Option<bool>,
+  pub log_level: Option<LevelFilter>,
+  pub stderr_log_level: Option<LevelFilter>,
+  pub idle_timeout: Option<u64>,
+  pub keep_alive_interval: Option<u64>,
+  pub quiet_hours: Vec<QuietHours>,
+}
+
+impl ConfigOverrides {
+  /// Replace the settings that are overridden.
+  pub fn apply(&self, config: &mut Config) {
+    self.merge(config, &self.as_config());
+  }
+  /// Put back the values from `file_config` for every setting that is overridden,
+  /// so that `config` can be saved without saving the overrides.
+  pub fn unapply(&self, config: &mut Config, file_config: &Config) {
+    self.merge(config, file_config);
+  }
+  /// Copy every overridden setting from `from` into `config`.
+  fn merge(&self, config: &mut Config, from: &Config) {
+    if self.api_key.is_some() {
+      config.api_key = from.api_key.clone();
+    }
+    if self.api_url.is_some() {
+      config.api_url = from.api_url.clone();
+    }
+    if !self.projects_files.is_empty() {
+      config.projects_files = from.projects_files.clone();
+    }
+    if self.symbol_file.is_some() {
+      config.symbol_file = from.symbol_file.clone();
+    }
+    if self.footprint_folder.is_some() {
+      config.footprint_folder = from.footprint_folder.clone();
+    }
+    if self.prune_mode.is_some() {
+      config.prune_mode = from.prune_mode;
+    }
+    if self.auto_track.is_some() {
+      config.auto_track = from.auto_track;
+    }
+    if self.log_level.is_some() {
+      config.log_level = from.log_level;
+    }
+    if self.stderr_log_level.is_some() {
+      config.stderr_log_level = from.stderr_log_level;
+    }
+    if self.idle_timeout.is_some() {
+      config.idle_timeout = from.idle_timeout;
+    }
+    if self.keep_alive_interval.is_some() {
+      config.keep_alive_interval = from.keep_alive_interval;
+    }
+    if !self.quiet_hours.is_empty() {
+      config.quiet_hours = from.quiet_hours.clone();
+    }
+  }
+  fn as_config(&self) -> Config {
+    Config {
+      api_key: self.api_key.clone(),
+      api_url: self.api_url.clone(),
+      projects_files: self.projects_files.clone(),
+      symbol_file: self.symbol_file.clone(),
+      footprint_folder: self.footprint_folder.clone(),
+      prune_mode: self.prune_mode.unwrap_or_default(),
+      auto_track: self.auto_track.unwrap_or_default(),
+      log_level: self.log_level,
+      stderr_log_level: self.stderr_log_level,
+      idle_timeout: self.idle_timeout,
+      keep_alive_interval: self.keep_alive_interval,
+      quiet_hours: self.quiet_hours.clone(),
+    }
+  }
+  /// Return the names of the overridden settings, as shown in the settings window.
+  pub fn overridden(&self) -> Vec<&'static str> {
+    [
+      ("API key", self.api_key.is_some()),
+      ("API URL", self.api_url.is_some()),
+      ("KiCAD projects", !self.projects_files.is_empty()),
+      ("symbol library", self.symbol_file.is_some()),
+      ("footprint library", self.footprint_folder.is_some()),
+      ("prune redundant backups", self.prune_mode.is_some()),
+      ("automatically track projects", self.auto_track.is_some()),
+      ("log level", self.log_level.is_some()),
+      ("stderr log level", self.stderr_log_level.is_some()),
+      ("idle timeout", self.idle_timeout.is_some()),
+      ("keep-alive interval", self.keep_alive_interval.is_some()),
+      ("quiet hours", !self.quiet_hours.is_empty()),
+    ]
+      .into_iter()
+      .filter(|(_, overridden)| *overridden)
+      .map(|(name, _)| name)
+      .collect()
+  }
+}
+
+fn load_ini(path: &Path) -> Result<Ini, anyhow::Error> {
+  if !fs::exists(path)? {
+    return Ok(Ini::new())
+  }
+  Ini::load_from_file(path).with_context(|| format!("Could not parse {:?}", path))
+}
+
+/// Parse a setting, or warn and return `None` if the value is bad.
+fn parse_setting<T: FromStr>(key: &str, value: &str, expected: &str) -> Option<T> {
+  match value.parse() {
+    Ok(value) => Some(value),
+    Err(_) => {
+      warn!("Ignoring {key} = {value:?} in the settings, it should be {expected}");
+      None
+    },
+  }
+}
+
+/// Bring a .kicad-wakatime.cfg written by an older version up to date.
+pub fn migrate(kicad_wakatime: &mut Ini) {
+  let settings = kicad_wakatime.entry(SETTINGS.map(String::from)).or_insert(Default::default());
+  // a bad version is treated as the oldest, migrating a file that is already current changes nothing
+  let version: u32 = settings.get("config_version")
+    .and_then(|version| parse_setting("config_version", version, "a number"))
+    .unwrap_or(0);
+  if version < 1 {
+    // before a .kicad_pro file had to be selected, the folder containing it was stored
+    if let Some(projects_folder) = settings.remove("projects_folder").filter(|f| !f.is_empty()) {
+      let projects_files = fs::read_dir(&projects_folder)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "kicad_pro"));
+      for projects_file in projects_files {
+        info!("Migrating projects_folder {projects_folder:?} to projects_file {projects_file:?}");
+        settings.append("projects_file", projects_file.to_string_lossy().to_string());
+      }
+    }
+    // empty values used to be written for unset settings
+    let empty_keys = settings.iter()
+      .filter(|(_, value)| value.is_empty())
+      .map(|(key, _)| key.to_string())
+      .collect::<Vec<_>>();
+    for key in empty_keys {
+      let _ = settings.remove_all(&key);
+    }
+  }
+  if version > CONFIG_VERSION {
+    warn!("config_version {version} is newer than this version of kicad-wakatime supports, some settings may be ignored");
+    return
+  }
+  settings.insert("config_version", CONFIG_VERSION.to_string());
+}
+
+/// Return whether a string looks like a WakaTime API key (a UUID, optionally prefixed with `waka_`).
+/// Wakapi and Hackatime use the same format.
+pub fn is_api_key(api_key: &str) -> bool {
+  static RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(waka_)?[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap()
+  });
+  RE.is_match(api_key)
+}
+
+/// Return whether a string is an absolute http(s) URL.
+pub fn is_api_url(api_url: &str) -> bool {
+  reqwest::Url::parse(api_url)
+    .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveTime;
+
+  use super::*;
+
+  fn settings(ini: &str) -> Ini {
+    Ini::load_from_str(&format!("[settings]\n{ini}")).unwrap()
+  }
+
+  const API_KEY: &str = "waka_01234567-89ab-cdef-0123-456789abcdef";
+
+  #[test]
+  fn round_trips_through_ini() {
+    let config = Config {
+      api_key: Some(API_KEY.to_string()),
+      api_url: Some("https://hackatime.hackclub.com/api/hackatime/v1".to_string()),
+      projects_files: vec![PathBuf::from("/boards/main/main.kicad_pro"), PathBuf::from("/boards/io/io.kicad_pro")],
+      symbol_file: Some(PathBuf::from("/libs/lab.kicad_sym")),
+      footprint_folder: Some(PathBuf::from("/libs/lab.pretty")),
+      prune_mode: PruneMode::DryRun,
+      auto_track: true,
+      log_level: Some(LevelFilter::Info),
+      stderr_log_level: Some(LevelFilter::Off),
+      idle_timeout: Some(0),
+      keep_alive_interval: Some(600),
+      quiet_hours: vec!["mon-fri 18:00-09:00".parse().unwrap(), "sat,sun".parse().unwrap()],
+    };
+    // keys of other WakaTime plugins have to survive a save
+    let mut wakatime = settings("debug = true");
+    let mut kicad_wakatime = Ini::new();
+    config.to_ini(&mut wakatime, &mut kicad_wakatime);
+    assert_eq!(Config::from_ini(&wakatime, &kicad_wakatime), config);
+    assert_eq!(wakatime.get_from(SETTINGS, "debug"), Some("true"));
+    assert_eq!(kicad_wakatime.get_from(SETTINGS, "config_version"), Some("1"));
+
+    // unset settings are removed rather than written empty
+    Config::default().to_ini(&mut wakatime, &mut kicad_wakatime);
+    assert_eq!(Config::from_ini(&wakatime, &kicad_wakatime), Config::default());
+    assert_eq!(wakatime.get_from(SETTINGS, "api_key"), None);
+    assert_eq!(kicad_wakatime.section(SETTINGS).unwrap().get_all("projects_file").count(), 0);
+  }
+
+  #[test]
+  fn migrates_version_0() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    fs::write(dir.join("board.kicad_pro"), "{}").unwrap();
+    fs::write(dir.join("notes.txt"), "").unwrap();
+    let mut kicad_wakatime = settings(&format!("projects_folder = {}\nsymbol_file =\nprune_backups = on", dir.display()));
+    migrate(&mut kicad_wakatime);
+    let settings = kicad_wakatime.section(SETTINGS).unwrap();
+    assert_eq!(settings.get_all("projects_file").collect::<Vec<_>>(), [dir.join("board.kicad_pro").to_str().unwrap()]);
+    assert_eq!(settings.get("projects_folder"), None);
+    assert_eq!(settings.get("symbol_file"), None);
+    assert_eq!(settings.get("prune_backups"), Some("on"));
+    assert_eq!(settings.get("config_version"), Some("1"));
+
+    // an up to date file is left as it is
+    let mut current = kicad_wakatime.clone();
+    migrate(&mut current);
+    assert_eq!(Config::from_ini(&Ini::new(), &current), Config::from_ini(&Ini::new(), &kicad_wakatime));
+  }
+
+  #[test]
+  fn skips_bad_values() {
+    let kicad_wakatime = settings("\
+      config_version = soon\n\
+      projects_file = /boards/main/main.kicad_pro\n\
+      prune_backups = sometimes\n\
+      auto_track_projects = yes\n\
+      log_level = loud\n\
+      stderr_log_level = error\n\
+      idle_timeout = 5m\n\
+      keep_alive_interval = 300\n\
+      quiet_hours = weekends\n\
+      quiet_hours = * 12:00-13:00\n");
+    let config = Config::from_ini(&settings(&format!("api_key = {API_KEY}")), &kicad_wakatime);
+    assert_eq!(config, Config {
+      api_key: Some(API_KEY.to_string()),
+      projects_files: vec![PathBuf::from("/boards/main/main.kicad_pro")],
+      stderr_log_level: Some(LevelFilter::Error),
+      keep_alive_interval: Some(300),
+      quiet_hours: vec![QuietHours {
+        days: [true; 7],
+        times: Some((NaiveTime::from_hms_opt(12, 0, 0).unwrap(), NaiveTime::from_hms_opt(13, 0, 0).unwrap())),
+      }],
+      ..Config::default()
+    });
+  }
+
+  #[test]
+  fn validates_settings() {
+    assert_eq!(Config::default().validate(), [
+      ConfigProblem::MissingApiKey,
+      ConfigProblem::MissingApiUrl,
+      ConfigProblem::NoProjects,
+    ]);
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let project = dir.join("board.kicad_pro");
+    let symbol_file = dir.join("lab.kicad_sym");
+    let footprint_folder = dir.join("lab.pretty");
+    fs::write(&project, "{}").unwrap();
+    fs::write(&symbol_file, "").unwrap();
+    fs::create_dir(&footprint_folder).unwrap();
+    let mut config = Config {
+      api_key: Some(API_KEY.to_string()),
+      api_url: Some("https://api.wakatime.com/api/v1".to_string()),
+      projects_files: vec![project],
+      symbol_file: Some(symbol_file),
+      footprint_folder: Some(footprint_folder),
+      ..Config::default()
+    };
+    assert_eq!(config.validate(), []);
+
+    config.api_key = Some("not a key".to_string());
+    config.api_url = Some("ftp://example.com".to_string());
+    config.projects_files = vec![dir.join("board.kicad_pcb"), dir.join("other.kicad_pro")];
+    config.symbol_file = Some(dir.join("missing.kicad_sym"));
+    config.footprint_folder = Some(dir.join("missing.pretty"));
+    assert_eq!(config.validate(), [
+      ConfigProblem::MalformedApiKey,
+      ConfigProblem::MalformedApiUrl("ftp://example.com".to_string()),
+      ConfigProblem::NotAProject(dir.join("board.kicad_pcb")),
+      ConfigProblem::MissingProject(dir.join("other.kicad_pro")),
+      ConfigProblem::MissingSymbolFile(dir.join("missing.kicad_sym")),
+      ConfigProblem::MissingFootprintFolder(dir.join("missing.pretty")),
+    ]);
+
+    // projects that KiCAD has open count when they are tracked automatically
+    config.projects_files = vec![];
+    config.auto_track = true;
+    assert!(!config.validate().contains(&ConfigProblem::NoProjects));
+  }
+
+  #[test]
+  fn saving_keeps_changes_made_on_disk() {
+    let temp = tempfile::tempdir().unwrap();
+    let wakatime_cfg = temp.path().join(".wakatime.cfg");
+    let kicad_wakatime_cfg = temp.path().join("kicad-wakatime.cfg");
+    fs::write(&wakatime_cfg, format!("[settings]\napi_key = {API_KEY}\napi_url = https://api.wakatime.com/api/v1\n")).unwrap();
+    let (base, _, _) = Config::load(&wakatime_cfg, &kicad_wakatime_cfg).unwrap();
+
+    // meanwhile, another program adds a key of its own and changes the API URL
+    fs::write(&wakatime_cfg, format!(
+      "[settings]\napi_key = {API_KEY}\napi_url = https://hackatime.hackclub.com/api/hackatime/v1\ndebug = true\n"
+    )).unwrap();
+    let ours = Config { auto_track: true, ..base.clone() };
+    let (saved, _, _) = Config::save(&base, &ours, &wakatime_cfg, &kicad_wakatime_cfg).unwrap();
+
+    let (loaded, wakatime, _) = Config::load(&wakatime_cfg, &kicad_wakatime_cfg).unwrap();
+    assert_eq!(loaded, saved);
+    assert!(loaded.auto_track);
+    assert_eq!(loaded.api_url.as_deref(), Some("https://hackatime.hackclub.com/api/hackatime/v1"));
+    assert_eq!(wakatime.get_from(SETTINGS, "debug"), Some("true"));
+  }
+
+  #[test]
+  fn validation_is_remembered_until_the_config_changes() {
+    let temp = tempfile::tempdir().unwrap();
+    let project = temp.path().join("board.kicad_pro");
+    fs::write(&project, "{}").unwrap();
+    let mut config = Config { projects_files: vec![project.clone()], ..Config::default() };
+    let validation = Validation::default();
+    assert!(!validation.problems(&config).contains(&ConfigProblem::MissingProject(project.clone())));
+
+    fs::remove_file(&project).unwrap();
+    assert!(!validation.problems(&config).contains(&ConfigProblem::MissingProject(project.clone())));
+
+    config.auto_track = true;
+    assert!(validation.problems(&config).contains(&ConfigProblem::MissingProject(project)));
+  }
+}
@@ -0,0 +1,168 @@
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use anyhow::bail;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use reqwest::blocking::{Client, Response};
+use reqwest::StatusCode;
+
+/// API URL used when none is set, the same one the WakaTime CLI uses.
+pub const DEFAULT_API_URL: &str = "https://api.wakatime.com/api/v1";
+
+/// A connection test running in the background, so that a slow server does not freeze the UI.
+pub struct ConnectionTest {
+  rx: Receiver<Result<Option<String>, String>>,
+  // the result, once the test has finished
+  pub result: Option<Result<Option<String>, String>>,
+}
+
+impl ConnectionTest {
+  pub fn start(api_key: String, api_url: String) -> Self {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      let result = test_connection(&api_key, &api_url).map_err(|e| format!("{e:#}"));
+      let _ = tx.send(result);
+    });
+    ConnectionTest { rx, result: None }
+  }
+  /// Return whether the test is still running, picking up the result if it has finished.
+  pub fn is_running(&mut self) -> bool {
+    if self.result.is_none() {
+      self.result = self.rx.try_recv().ok();
+    }
+    self.result.is_none()
+  }
+}
+
+/// Check an API key against the server at an API URL.
+/// Works with WakaTime and the servers that copy its API, like Wakapi and Hackatime.
+/// Returns the name of the user the key belongs to, if the server says.
+pub fn test_connection(api_key: &str, api_url: &str) -> Result<Option<String>, anyhow::Error> {
+  let api_url = api_url.trim_end_matches('/');
+  let client = Client::builder()
+    .user_agent(format!("kicad-wakatime/{}", env!("CARGO_PKG_VERSION")))
+    .timeout(Duration::from_secs(15))
+    .build()?;
+  let get = |url: &str| -> Result<Response, anyhow::Error> {
+    let res = client.get(url)
+      .header("authorization", format!("Basic {}", BASE64.encode(api_key)))
+      .send()?;
+    Ok(res)
+  };
+  let res = get(&format!("{api_url}/users/current"))?;
+  // not every server has this endpoint, but they all have the one the status bar uses
+  if res.status() == StatusCode::NOT_FOUND {
+    check_status(get(&format!("{api_url}/users/current/statusbar/today"))?)?;
+    return Ok(None)
+  }
+  let json = check_status(res)?.json::<serde_json::Value>()?;
+  let user = &json["data"];
+  Ok(["username", "display_name", "email"].iter()
+    .find_map(|key| user[key].as_str().filter(|name| !name.is_empty()))
+    .map(str::to_string))
+}
+
+/// Turn an unsuccessful response into an error explaining it.
+fn check_status(res: Response) -> Result<Response, anyhow::Error> {
+  let status = res.status();
+  if status.is_success() {
+    return Ok(res)
+  }
+  let url = res.url().clone();
+  let body = res.text().unwrap_or_default();
+  let body = body.trim();
+  match status {
+    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => bail!("The server rejected the API key ({status})"),
+    StatusCode::NOT_FOUND => bail!("{url} was not found ({status}), check the API URL"),
+    _ if body.is_empty() => bail!("{url} returned {status}"),
+    // error pages can be long
+    _ => bail!("{url} returned {status}: {}", body.chars().take(200).collect::<String>()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{BufRead, BufReader, Write};
+  use std::net::TcpListener;
+
+  use super::*;
+
+  const API_KEY: &str = "waka_01234567-89ab-cdef-0123-456789abcdef";
+
+  /// Start a server that answers each request with the response for its path (404 for other
+  /// paths), and return its API URL and the requests it got, as "path authorization" lines.
+  fn mock_server(responses: &'static [(&'static str, &'static str, &'static str)]) -> (String, Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}/api/v1", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines().map(Result::unwrap);
+        let path = lines.next().unwrap().split(' ').nth(1).unwrap().to_string();
+        let authorization = lines.by_ref()
+          .take_while(|line| !line.is_empty())
+          .find_map(|line| line.strip_prefix("authorization: ").map(str::to_string))
+          .unwrap_or_default();
+        let _ = tx.send(format!("{path} {authorization}"));
+        let (status, body) = responses.iter()
+          .find(|(p, _, _)| *p == path)
+          .map(|(_, status, body)| (*status, *body))
+          .unwrap_or(("404 Not Found", ""));
+        write!(stream, "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()).unwrap();
+      }
+    });
+    (api_url, rx)
+  }
+
+  #[test]
+  fn returns_username() {
+    let (api_url, requests) = mock_server(&[
+      ("/api/v1/users/current", "200 OK", r#"{"data": {"username": "", "display_name": "Ada", "email": "ada@example.com"}}"#),
+    ]);
+    assert_eq!(test_connection(API_KEY, &format!("{api_url}/")).unwrap(), Some("Ada".to_string()));
+    assert_eq!(requests.recv().unwrap(), format!("/api/v1/users/current Basic {}", BASE64.encode(API_KEY)));
+  }
+
+  #[test]
+  fn rejects_api_key() {
+    let (api_url, _) = mock_server(&[("/api/v1/users/current", "401 Unauthorized", "")]);
+    let e = test_connection(API_KEY, &api_url).unwrap_err();
+    assert_eq!(e.to_string(), "The server rejected the API key (401 Unauthorized)");
+  }
+
+  #[test]
+  fn falls_back_to_statusbar() {
+    let (api_url, requests) = mock_server(&[
+      ("/api/v1/users/current/statusbar/today", "200 OK", r#"{"data": {}}"#),
+    ]);
+    assert_eq!(test_connection(API_KEY, &api_url).unwrap(), None);
+    assert!(requests.recv().unwrap().starts_with("/api/v1/users/current "));
+    assert!(requests.recv().unwrap().starts_with("/api/v1/users/current/statusbar/today "));
+
+    // a wrong API URL has neither
+    let (api_url, _) = mock_server(&[]);
+    let e = test_connection(API_KEY, &api_url).unwrap_err();
+    assert!(e.to_string().ends_with("was not found (404 Not Found), check the API URL"), "{e}");
+  }
+
+  #[test]
+  fn reports_server_errors() {
+    let (api_url, _) = mock_server(&[
+      ("/api/v1/users/current", "503 Service Unavailable", "  down for maintenance\n"),
+    ]);
+    let e = test_connection(API_KEY, &api_url).unwrap_err();
+    assert_eq!(e.to_string(), format!("{api_url}/users/current returned 503 Service Unavailable: down for maintenance"));
+  }
+
+  #[test]
+  fn reports_connection_refused() {
+    // nothing listens on a port that was just freed
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let api_url = format!("http://{}/api/v1", listener.local_addr().unwrap());
+    drop(listener);
+    let e = test_connection(API_KEY, &api_url).unwrap_err();
+    assert!(format!("{e:#}").contains("error sending request"), "{e:#}");
+  }
+}
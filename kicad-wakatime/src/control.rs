@@ -0,0 +1,284 @@
+use std::io::{BufRead, BufReader, Write};
+#[cfg(not(unix))]
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+use anyhow::Context;
+use log::{debug, error, info};
+use serde_json::{json, Value};
+
+use crate::{paths, Plugin};
+
+// how long a client waits for the tracker to answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A request from a control socket client, waiting for the tracker to answer it.
+pub struct Request {
+  pub command: String,
+  // the whole request, for commands that take arguments
+  pub args: Value,
+  reply: Sender<Value>,
+}
+
+impl Request {
+  pub fn reply(self, response: Value) {
+    // the client may have given up already
+    let _ = self.reply.send(response);
+  }
+}
+
+/// The listening end of the control socket.
+/// Connections are handled on background threads, which hand the requests to the tracker.
+///
+/// On Linux and macOS this is a Unix socket. Windows has no Unix sockets that every version
+/// supports, so there it is a TCP port on localhost, written to a file in the state folder
+/// along with a token that requests have to include, so that other users can't send commands.
+///
+/// The protocol is one line of JSON each way. Requests look like `{"command": "status"}`,
+/// where the command is `status`, `pause`, `resume`, `rescan`, `diagnostics` or `activate`.
+/// `pause` resumes by itself after `"minutes"`, if given.
+/// `activate` brings the window to the front and tracks the `"projects"` given with it.
+/// Responses always have `"ok"`, and `"error"` when it is false.
+pub struct ControlServer {
+  rx: Receiver<Request>,
+  // the socket, or the file with the port
+  path: PathBuf,
+}
+
+impl ControlServer {
+  #[cfg(unix)]
+  pub fn start() -> Result<Self, anyhow::Error> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    let path = paths::control_socket_path();
+    if path.exists() {
+      if UnixStream::connect(&path).is_ok() {
+        anyhow::bail!("Another kicad-wakatime is already listening on {:?}", path);
+      }
+      // left behind by a kicad-wakatime that did not exit cleanly
+      fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    info!("Listening for control commands on {:?}", path);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue; };
+        let tx = tx.clone();
+        thread::spawn(move || {
+          let result = stream.set_read_timeout(Some(TIMEOUT)).map_err(anyhow::Error::from)
+            .and_then(|()| handle_connection(BufReader::new(&stream), &stream, tx, None));
+          if let Err(e) = result {
+            debug!("Control connection failed: {e:?}");
+          }
+        });
+      }
+    });
+    Ok(ControlServer { rx, path })
+  }
+  #[cfg(not(unix))]
+  pub fn start() -> Result<Self, anyhow::Error> {
+    use std::collections::hash_map::RandomState;
+    use std::fs;
+    use std::hash::{BuildHasher, Hasher};
+    // the instance lock makes sure no other kicad-wakatime is using the file
+    let path = paths::control_port_path();
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let port = listener.local_addr()?.port();
+    // RandomState is seeded randomly for every process
+    let token = format!("{:016x}{:016x}", RandomState::new().build_hasher().finish(), RandomState::new().build_hasher().finish());
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(&path, format!("{port} {token}"))?;
+    info!("Listening for control commands on port {port} (see {:?})", path);
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue; };
+        let tx = tx.clone();
+        let token = token.clone();
+        thread::spawn(move || {
+          let result = stream.set_read_timeout(Some(TIMEOUT)).map_err(anyhow::Error::from)
+            .and_then(|()| handle_connection(BufReader::new(&stream), &stream, tx, Some(&token)));
+          if let Err(e) = result {
+            debug!("Control connection failed: {e:?}");
+          }
+        });
+      }
+    });
+    Ok(ControlServer { rx, path })
+  }
+  /// Return the next request waiting to be answered, if any.
+  pub fn try_recv(&self) -> Option<Request> {
+    self.rx.try_recv().ok()
+  }
+}
+
+impl Drop for ControlServer {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+/// Answer one request. If there is a token, requests without it are refused.
+fn handle_connection(
+  mut reader: impl BufRead,
+  mut writer: impl Write,
+  tx: Sender<Request>,
+  token: Option<&str>,
+) -> Result<(), anyhow::Error> {
+  let mut line = String::new();
+  reader.read_line(&mut line)?;
+  let response = match serde_json::from_str::<Value>(&line) {
+    Ok(request) if token.is_some_and(|token| request["token"] != token) => {
+      json!({ "ok": false, "error": "wrong or missing \"token\"" })
+    },
+    Ok(request) => match request["command"].as_str() {
+      Some(command) => {
+        let (reply, replies) = mpsc::channel();
+        tx.send(Request { command: command.to_string(), args: request.clone(), reply })?;
+        replies.recv_timeout(TIMEOUT)
+          .unwrap_or_else(|_| json!({ "ok": false, "error": "kicad-wakatime did not answer" }))
+      },
+      None => json!({ "ok": false, "error": "missing \"command\"" }),
+    },
+    Err(e) => json!({ "ok": false, "error": format!("invalid JSON: {e}") }),
+  };
+  writeln!(writer, "{response}")?;
+  Ok(())
+}
+
+/// Send a command to the running kicad-wakatime and return its response.
+pub fn send(command: &str) -> Result<Value, anyhow::Error> {
+  request(&json!({ "command": command }))
+}
+
+/// Send a request with arguments to the running kicad-wakatime and return its response.
+#[cfg(unix)]
+pub fn request(request: &Value) -> Result<Value, anyhow::Error> {
+  let path = paths::control_socket_path();
+  let stream = UnixStream::connect(&path)
+    .with_context(|| format!("Could not connect to {:?}, is kicad-wakatime running?", path))?;
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  writeln!(&stream, "{request}")?;
+  let mut line = String::new();
+  BufReader::new(&stream).read_line(&mut line)?;
+  Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(not(unix))]
+pub fn request(request: &Value) -> Result<Value, anyhow::Error> {
+  let path = paths::control_port_path();
+  let contents = std::fs::read_to_string(&path)
+    .with_context(|| format!("Could not read {:?}, is kicad-wakatime running?", path))?;
+  let (port, token) = contents.trim().split_once(' ').with_context(|| format!("{:?} is not valid", path))?;
+  let port: u16 = port.parse().with_context(|| format!("{:?} is not valid", path))?;
+  let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+    .with_context(|| format!("Could not connect to port {port}, is kicad-wakatime running?"))?;
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  let mut request = request.clone();
+  request["token"] = Value::from(token);
+  writeln!(&stream, "{request}")?;
+  let mut line = String::new();
+  BufReader::new(&stream).read_line(&mut line)?;
+  Ok(serde_json::from_str(&line)?)
+}
+
+impl Plugin {
+  /// Start listening on the control socket. Tracking works without it, so failures are only logged.
+  pub fn start_control_server(&mut self) {
+    match ControlServer::start() {
+      Ok(server) => self.control_server = Some(server),
+      Err(e) => error!("Could not start the control socket: {e:#}"),
+    }
+  }
+  /// Track the projects given to another start of kicad-wakatime, like `--project`
+  /// they are not saved.
+  fn track_handed_over_projects(&mut self, projects_files: Vec<PathBuf>) -> Result<(), anyhow::Error> {
+    let new_projects_files: Vec<PathBuf> = projects_files.into_iter()
+      .filter(|p| !self.config.projects_files.contains(p))
+      .collect();
+    if new_projects_files.is_empty() {
+      return Ok(())
+    }
+    self.overrides.projects_files = self.config.projects_files.iter()
+      .cloned()
+      .chain(new_projects_files)
+      .collect();
+    self.overrides.apply(&mut self.config);
+    self.watch_projects()
+  }
+  /// Answer the requests that came in on the control socket since the last call.
+  pub fn handle_control_requests(&mut self) {
+    while let Some(request) = self.control_server.as_ref().and_then(ControlServer::try_recv) {
+      debug!("Control command: {}", request.command);
+      let response = self.control_response(&request.command, &request.args);
+      request.reply(response);
+    }
+  }
+  fn control_response(&mut self, command: &str, args: &Value) -> Value {
+    match command {
+      "status" => {
+        let queue_length = self.offline_count().map_err(|e| debug!("{e:?}")).ok();
+        json!({
+          "ok": true,
+          "status": self.status(),
+          "paused": self.pause.is_some(),
+          "paused_until": self.pause.and_then(|p| p.until).map(|t| t.to_rfc3339()),
+          "quiet_hours": self.in_quiet_hours(),
+          "project": Some(&self.focused_project).filter(|p| !p.is_empty()),
+          "document": Some(&self.full_path).filter(|p| !p.as_os_str().is_empty()),
+          "last_heartbeat": self.last_sent_time_chrono.map(|t| t.to_rfc3339()),
+          "last_heartbeat_file": Some(&self.last_sent_file).filter(|f| !f.is_empty()),
+          "queue_length": queue_length,
+          "projects": self.projects.iter().map(|p| &p.file).collect::<Vec<_>>(),
+        })
+      },
+      "pause" => {
+        info!("Pausing from the control socket");
+        let minutes = match &args["minutes"] {
+          Value::Null => Ok(None),
+          minutes => minutes.as_u64().map(Some).ok_or("\"minutes\" should be a whole number"),
+        };
+        match minutes.map_err(anyhow::Error::msg).and_then(|minutes| self.pause(minutes)) {
+          Ok(()) => json!({ "ok": true }),
+          Err(e) => json!({ "ok": false, "error": format!("{e:#}") }),
+        }
+      },
+      "resume" => {
+        info!("Resuming from the control socket");
+        match self.resume() {
+          Ok(()) => json!({ "ok": true }),
+          Err(e) => json!({ "ok": false, "error": format!("{e:#}") }),
+        }
+      },
+      "rescan" => {
+        info!("Rescanning projects from the control socket");
+        match self.watch_projects() {
+          Ok(()) => json!({ "ok": true }),
+          Err(e) => json!({ "ok": false, "error": format!("{e:#}") }),
+        }
+      },
+      "diagnostics" => json!({ "ok": true, "diagnostics": self.diagnostics_info() }),
+      "activate" => {
+        info!("Another start of kicad-wakatime handed over to this one");
+        self.focus_requested = true;
+        let projects_files = args["projects"].as_array()
+          .into_iter()
+          .flatten()
+          .filter_map(Value::as_str)
+          .map(PathBuf::from)
+          .collect();
+        match self.track_handed_over_projects(projects_files) {
+          Ok(()) => json!({ "ok": true }),
+          Err(e) => json!({ "ok": false, "error": format!("{e:#}") }),
+        }
+      },
+      _ => json!({ "ok": false, "error": format!("unknown command {command:?}") }),
+    }
+  }
+}
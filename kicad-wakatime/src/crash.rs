@@ -0,0 +1,50 @@
+use std::backtrace::Backtrace;
+use std::fs;
+use std::io;
+use std::panic::{self, PanicHookInfo};
+use std::thread;
+use chrono::Local;
+
+use crate::scrub::scrub;
+use crate::{env_consts, paths};
+
+/// Save the details of panics to the crash file, as well as doing whatever was done before.
+/// The crash file is kept until the user dismisses it, so the next start can show it.
+pub fn install_hook() {
+  let previous_hook = panic::take_hook();
+  panic::set_hook(Box::new(move |info| {
+    let _ = save(info);
+    previous_hook(info);
+  }));
+}
+
+fn save(info: &PanicHookInfo) -> Result<(), io::Error> {
+  let message = match info.payload().downcast_ref::<&str>() {
+    Some(message) => message.to_string(),
+    None => match info.payload().downcast_ref::<String>() {
+      Some(message) => message.clone(),
+      None => String::from("(no message)"),
+    },
+  };
+  let location = info.location().map(|l| l.to_string()).unwrap_or_default();
+  let report = format!(
+    "kicad-wakatime {} crashed at {}\n(os, arch) = {:?}\nthread '{}' panicked at {location}:\n{message}\n\n{}",
+    env!("CARGO_PKG_VERSION"),
+    Local::now().to_rfc3339(),
+    env_consts(),
+    thread::current().name().unwrap_or("unnamed"),
+    Backtrace::force_capture(),
+  );
+  fs::create_dir_all(paths::state_dir())?;
+  fs::write(paths::crash_path(), scrub(&report))
+}
+
+/// Return the details of the last crash, if it has not been dismissed.
+pub fn last_crash() -> Option<String> {
+  fs::read_to_string(paths::crash_path()).ok()
+}
+
+/// Forget the last crash.
+pub fn dismiss() -> Result<(), io::Error> {
+  fs::remove_file(paths::crash_path())
+}
@@ -0,0 +1,153 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use ini::Ini;
+use log::info;
+use serde_json::{json, Value};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::crash;
+use crate::history::{HistoryEntry, Query};
+use crate::kicad_settings::kicad_config_dirs;
+use crate::scrub::scrub;
+use crate::{env_consts, paths, Plugin};
+
+// number of heartbeats from the history included
+const RECENT_HEARTBEATS: usize = 20;
+// environment variables that decide how window titles can be read
+const DISPLAY_VARS: &[&str] = &[
+  "XDG_SESSION_TYPE",
+  "XDG_CURRENT_DESKTOP",
+  "WAYLAND_DISPLAY",
+  "DISPLAY",
+  "HYPRLAND_INSTANCE_SIGNATURE",
+  "GDK_BACKEND",
+];
+
+/// Return the default name of a diagnostics bundle.
+pub fn default_file_name() -> String {
+  format!("kicad-wakatime-diagnostics-{}.zip", chrono::Local::now().format("%Y%m%d-%H%M%S"))
+}
+
+/// Write a .zip file for attaching to a bug report, with the logs, the last crash, redacted copies
+/// of the config files, and `info` (see [`Plugin::diagnostics_info`]).
+pub fn export(path: &Path, info: &Value) -> Result<(), anyhow::Error> {
+  let mut zip = ZipWriter::new(File::create(path)?);
+  let options = SimpleFileOptions::default();
+  zip.start_file("diagnostics.json", options)?;
+  zip.write_all(serde_json::to_string_pretty(info)?.as_bytes())?;
+  for log_path in log_paths()? {
+    let name = log_path.file_name().unwrap().to_string_lossy();
+    // logs from older versions were not scrubbed
+    zip.start_file(name, options)?;
+    zip.write_all(scrub(&String::from_utf8_lossy(&fs::read(&log_path)?)).as_bytes())?;
+  }
+  if let Some(last_crash) = crash::last_crash() {
+    zip.start_file("crash.txt", options)?;
+    zip.write_all(last_crash.as_bytes())?;
+  }
+  for (name, cfg_path) in [
+    ("wakatime.cfg", paths::wakatime_cfg_path()),
+    ("kicad-wakatime.cfg", paths::kicad_wakatime_cfg_path()),
+  ] {
+    if !cfg_path.is_file() {
+      continue
+    }
+    // a config file that can't be parsed is one of the things a bug report should show
+    let contents = redacted_config(&cfg_path)
+      .unwrap_or_else(|e| scrub(&format!("; could not read {:?}: {e:#}\n", cfg_path)));
+    zip.start_file(name, options)?;
+    zip.write_all(contents.as_bytes())?;
+  }
+  zip.finish()?;
+  Ok(())
+}
+
+/// Return the current and previous log files.
+fn log_paths() -> Result<Vec<PathBuf>, anyhow::Error> {
+  let log_name = paths::log_path().file_name().unwrap().to_string_lossy().to_string();
+  if !paths::state_dir().is_dir() {
+    return Ok(vec![])
+  }
+  let mut log_paths: Vec<PathBuf> = fs::read_dir(paths::state_dir())?
+    .flatten()
+    .map(|entry| entry.path())
+    .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().starts_with(&log_name)))
+    .collect();
+  log_paths.sort();
+  Ok(log_paths)
+}
+
+/// Return a config file with the values of secret-looking keys replaced.
+fn redacted_config(path: &Path) -> Result<String, anyhow::Error> {
+  let mut ini = Ini::load_from_file(path)?;
+  for (_, properties) in ini.iter_mut() {
+    let secret_keys: Vec<String> = properties.iter()
+      .map(|(key, _)| key.to_string())
+      .filter(|key| ["key", "token", "password", "secret"].iter().any(|s| key.to_lowercase().contains(s)))
+      .collect();
+    for key in secret_keys {
+      properties.insert(key, "<redacted>");
+    }
+  }
+  let mut contents = vec![];
+  ini.write_to(&mut contents)?;
+  Ok(scrub(&String::from_utf8_lossy(&contents)))
+}
+
+impl Plugin {
+  /// Return what kicad-wakatime knows about its environment and what it is tracking.
+  pub fn diagnostics_info(&self) -> Value {
+    let display: serde_json::Map<String, Value> = DISPLAY_VARS.iter()
+      .map(|var| (var.to_string(), env::var(var).map(Value::from).unwrap_or(Value::Null)))
+      .collect();
+    json!({
+      "version": self.version,
+      "env_consts": env_consts(),
+      "window_backend": self.window_backend(),
+      "display": display,
+      "kicad_config_dirs": kicad_config_dirs(),
+      "kicad_common": self.kicad_common.as_ref().map(|k| &k.path),
+      "kicad_settings_problems": self.kicad_settings_problems,
+      "cli_path": self.cli_path(env_consts()),
+      "status": self.status(),
+      "idle_backend": self.idle.backend(),
+      "idle_timeout": self.config.idle_timeout().map(|t| t.as_secs()),
+      "keep_alive_interval": self.config.keep_alive_interval().map(|t| t.as_secs()),
+      "overridden_settings": self.overrides.overridden(),
+      "settings_problems": self.config.validate().iter().map(|p| scrub(&p.to_string())).collect::<Vec<_>>(),
+      "projects": self.projects.iter().map(|p| json!({
+        "file": p.file,
+        "name": p.name,
+        "backups_folder": p.backups_folder,
+        "backups": p.backup_index.len(),
+        "full_paths": p.full_paths,
+      })).collect::<Vec<_>>(),
+      "suggested_projects": self.suggested_projects,
+      "focused_project": self.focused_project,
+      "full_path": self.full_path,
+      "title_trace": self.title_trace.iter()
+        .map(|(time, title)| json!({ "time": time.to_rfc3339(), "title": title }))
+        .collect::<Vec<_>>(),
+      "last_heartbeat": self.last_sent_time_chrono.map(|t| t.to_rfc3339()),
+      "last_heartbeat_file": self.last_sent_file,
+      "recent_heartbeats": self.history.query(&Query { limit: Some(RECENT_HEARTBEATS), ..Query::default() })
+        .map(|entries| entries.iter().map(HistoryEntry::to_json).collect::<Vec<_>>())
+        .unwrap_or_default(),
+    })
+  }
+  /// Ask where to save a diagnostics bundle, then save it.
+  pub fn export_diagnostics(&self) -> Result<(), anyhow::Error> {
+    let Some(path) = rfd::FileDialog::new()
+      .set_file_name(default_file_name())
+      .add_filter("Zip archive", &["zip"])
+      .save_file() else {
+      return Ok(())
+    };
+    export(&path, &self.diagnostics_info())?;
+    info!("Saved diagnostics to {:?}", path);
+    Ok(())
+  }
+}
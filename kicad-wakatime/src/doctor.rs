@@ -0,0 +1,239 @@
+use std::env;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+
+use crate::config::Config;
+use crate::connection::{self, DEFAULT_API_URL};
+use crate::kicad_settings::KicadCommon;
+use crate::project::Project;
+use crate::{env_consts, Plugin};
+
+// how long to wait for the user to focus a KiCAD window
+const FOCUS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Prints the results of the checks, and counts the failures.
+#[derive(Default)]
+struct Report {
+  failures: usize,
+}
+
+impl Report {
+  fn pass(&self, message: impl AsRef<str>) {
+    println!("[ OK ] {}", message.as_ref());
+  }
+  fn warn(&self, message: impl AsRef<str>) {
+    println!("[WARN] {}", message.as_ref());
+  }
+  fn fail(&mut self, message: impl AsRef<str>) {
+    println!("[FAIL] {}", message.as_ref());
+    self.failures += 1;
+  }
+  fn section(&self, name: &str) {
+    println!("\n{name}");
+  }
+}
+
+/// Check everything kicad-wakatime needs, printing a report.
+/// Returns whether every check passed.
+pub fn run(plugin: &mut Plugin) -> bool {
+  let mut report = Report::default();
+  println!("kicad-wakatime {} doctor", plugin.version);
+  println!("(os, arch) = {:?}", env_consts());
+  report.section("Settings");
+  match plugin.load_config() {
+    Ok(()) => check_config(&mut report, &plugin.config),
+    Err(e) => report.fail(format!("Could not load the settings: {e:#}")),
+  }
+  report.section("WakaTime CLI");
+  check_cli(&mut report, plugin);
+  report.section("Projects");
+  check_projects(&mut report, plugin);
+  report.section("KiCAD");
+  check_kicad_settings(&mut report, plugin);
+  report.section("Window titles");
+  check_display_server(&mut report, plugin);
+  check_window_title(&mut report, plugin);
+  check_idle(&report, plugin);
+  report.section("Server");
+  check_connection(&mut report, &plugin.config);
+  println!();
+  if report.failures == 0 {
+    println!("Everything looks fine.");
+  } else {
+    println!("{} check(s) failed.", report.failures);
+  }
+  report.failures == 0
+}
+
+fn check_config(report: &mut Report, config: &Config) {
+  let problems = config.validate();
+  if problems.is_empty() {
+    report.pass("Settings are valid");
+  }
+  for problem in problems {
+    report.fail(problem.to_string());
+  }
+}
+
+fn check_cli(report: &mut Report, plugin: &Plugin) {
+  let cli_path = plugin.cli_path(env_consts());
+  if !cli_path.is_file() {
+    report.fail(format!("{:?} does not exist, start kicad-wakatime once to download it", cli_path));
+    return
+  }
+  let mut cli = Command::new(&cli_path);
+  cli.arg("--version");
+  #[cfg(windows)]
+  {
+    cli.creation_flags(0x08000000); // CREATE_NO_WINDOW
+  }
+  match cli.output() {
+    Ok(output) if output.status.success() => {
+      let version = String::from_utf8_lossy(&output.stdout);
+      report.pass(format!("{:?} is version {}", cli_path, version.trim()));
+    },
+    Ok(output) => report.fail(format!("{:?} --version failed with {}", cli_path, output.status)),
+    Err(e) => report.fail(format!("Could not run {:?}: {e}", cli_path)),
+  }
+  match plugin.offline_count() {
+    Ok(0) => report.pass("No heartbeats are waiting to be sent"),
+    Ok(count) => report.warn(format!("{count} heartbeat(s) are waiting to be sent")),
+    Err(e) => report.warn(format!("Could not count the heartbeats waiting to be sent: {e}")),
+  }
+}
+
+fn check_projects(report: &mut Report, plugin: &Plugin) {
+  // missing .kicad_pro files are reported with the settings
+  for projects_file in plugin.config.projects_files.iter().filter(|f| f.is_file()) {
+    let mut project = Project::new(projects_file.clone());
+    project.load_config();
+    report.pass(format!("Tracking {} ({:?})", project.name, project.file));
+    match project.duplicate_file_names() {
+      Ok(duplicates) if duplicates.is_empty() => {},
+      Ok(duplicates) => report.fail(format!(
+        "{} has more than one file named {}, so they can't be told apart",
+        project.name,
+        duplicates.join(", ")
+      )),
+      Err(e) => report.fail(format!("Could not look through {:?}: {e}", project.folder)),
+    }
+    let libraries = project.config.symbol_files.iter().chain(&project.config.footprint_folders);
+    for library in libraries.filter(|l| !l.exists()) {
+      report.fail(format!("{:?} from {}'s .kicad-wakatime does not exist", library, project.name));
+    }
+  }
+}
+
+fn check_kicad_settings(report: &mut Report, plugin: &Plugin) {
+  match KicadCommon::load() {
+    Ok(Some(kicad_common)) => {
+      let problems = kicad_common.problems();
+      if problems.is_empty() {
+        report.pass(format!("Backup settings in {:?} are fine", kicad_common.path));
+      }
+      for problem in problems {
+        report.fail(format!("{problem} ({:?})", kicad_common.path));
+      }
+    },
+    Ok(None) => report.warn("Could not find kicad_common.json, is KiCAD installed?"),
+    Err(e) => report.fail(format!("Could not read kicad_common.json: {e}")),
+  }
+  if plugin.config.auto_track {
+    report.pass("Projects open in KiCAD are tracked automatically");
+  }
+}
+
+fn check_display_server(report: &mut Report, plugin: &Plugin) {
+  if env::consts::OS != "linux" {
+    report.pass(format!("Titles are read with {}", plugin.window_backend()));
+    return
+  }
+  let wayland = env::var("XDG_SESSION_TYPE").is_ok_and(|t| t == "wayland") ||
+    env::var_os("WAYLAND_DISPLAY").is_some();
+  if !wayland && env::var_os("DISPLAY").is_none() {
+    report.fail("No X11 or Wayland display found");
+  } else if !wayland {
+    report.pass(format!("X11 session, titles are read with {}", plugin.window_backend()));
+  } else if plugin.window_backend() == "hyprctl" {
+    report.pass("Hyprland session, titles are read with hyprctl");
+  } else if env::var_os("DISPLAY").is_none() {
+    report.fail("Wayland session without XWayland, the titles of KiCAD's windows can't be read");
+  } else {
+    report.warn("Wayland session, titles can only be read from XWayland windows");
+    match kicad_gdk_backends() {
+      backends if backends.is_empty() => report.warn("KiCAD is not running, start it with GDK_BACKEND=x11"),
+      backends if backends.iter().all(|b| b.as_deref() == Some("x11")) => {
+        report.pass("KiCAD is running under XWayland (GDK_BACKEND=x11)");
+      },
+      _ => report.fail("KiCAD is running as a Wayland window, start it with GDK_BACKEND=x11"),
+    }
+  }
+}
+
+/// Return the GDK_BACKEND of each running KiCAD process.
+fn kicad_gdk_backends() -> Vec<Option<String>> {
+  let Ok(processes) = std::fs::read_dir("/proc") else { return vec![] };
+  processes
+    .flatten()
+    .filter(|p| std::fs::read_to_string(p.path().join("comm"))
+      .is_ok_and(|comm| matches!(comm.trim(), "kicad" | "eeschema" | "pcbnew")))
+    .filter_map(|p| std::fs::read(p.path().join("environ")).ok())
+    .map(|environ| environ
+      .split(|b| *b == 0)
+      .find_map(|var| var.strip_prefix(b"GDK_BACKEND="))
+      .map(|backend| String::from_utf8_lossy(backend).to_string()))
+    .collect()
+}
+
+fn check_window_title(report: &mut Report, plugin: &mut Plugin) {
+  println!("Focus a KiCAD editor window within {} seconds...", FOCUS_TIMEOUT.as_secs());
+  let start = Instant::now();
+  let mut last_title = None;
+  while start.elapsed() < FOCUS_TIMEOUT {
+    if let Some(title) = plugin.active_window_title() {
+      if title.contains(" — ") && title.contains("Editor") {
+        report.pass(format!("Read KiCAD's title with {}: {title:?}", plugin.window_backend()));
+        return
+      }
+      last_title = Some(title);
+    }
+    thread::sleep(Duration::from_millis(250));
+  }
+  match last_title {
+    Some(title) => report.fail(format!(
+      "{} never returned a KiCAD title, the last one was {title:?}",
+      plugin.window_backend()
+    )),
+    None => report.fail(format!("{} could not read any window title", plugin.window_backend())),
+  }
+}
+
+fn check_idle(report: &Report, plugin: &Plugin) {
+  match plugin.config.idle_timeout() {
+    Some(timeout) => report.pass(format!(
+      "Heartbeats stop after {}s idle, found with {}",
+      timeout.as_secs(),
+      plugin.idle.backend()
+    )),
+    None => report.warn("Idle detection is off, time is counted while KiCAD is focused even if you walk away"),
+  }
+  if plugin.idle.backend() == "cursor movement" {
+    report.warn("The system idle time can't be read, only mouse movement counts as activity");
+  }
+}
+
+fn check_connection(report: &mut Report, config: &Config) {
+  let api_url = config.api_url.clone().unwrap_or(String::from(DEFAULT_API_URL));
+  let Some(ref api_key) = config.api_key else {
+    report.fail("Can't connect to the server without an API key");
+    return
+  };
+  match connection::test_connection(api_key, &api_url) {
+    Ok(Some(username)) => report.pass(format!("Connected to {api_url} as {username}")),
+    Ok(None) => report.pass(format!("Connected to {api_url}")),
+    Err(e) => report.fail(format!("Could not connect to {api_url}: {e:#}")),
+  }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use log::info;
+
+use crate::Plugin;
+
+// how often the focused window is checked without the window's repaints driving the loop
+const INTERVAL: Duration = Duration::from_millis(100);
+
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+#[cfg(windows)]
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_terminate(_signal: libc::c_int) {
+  // only async-signal-safe things can happen here
+  TERMINATE.store(true, Ordering::SeqCst);
+}
+
+#[cfg(windows)]
+extern "system" fn on_console_event(_event: u32) -> i32 {
+  TERMINATE.store(true, Ordering::SeqCst);
+  // this runs on a thread of its own, and when the console is closed or the user logs off,
+  // Windows ends the process as soon as it returns
+  while !FINISHED.load(Ordering::SeqCst) {
+    thread::sleep(INTERVAL);
+  }
+  // handled, so the next handler (which kills the process) is not called
+  1
+}
+
+/// Make SIGTERM and SIGINT, or on Windows Ctrl+C and closing the console,
+/// stop the loop instead of killing the process.
+fn handle_termination() {
+  #[cfg(unix)]
+  unsafe {
+    let handler = on_terminate as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    libc::signal(libc::SIGTERM, handler);
+    libc::signal(libc::SIGINT, handler);
+  }
+  #[cfg(windows)]
+  {
+    #[link(name = "kernel32")]
+    extern "system" {
+      fn SetConsoleCtrlHandler(handler: Option<extern "system" fn(u32) -> i32>, add: i32) -> i32;
+    }
+    unsafe { SetConsoleCtrlHandler(Some(on_console_event), 1) };
+  }
+}
+
+/// Track KiCAD without a window, until the process is asked to terminate.
+pub fn run(plugin: &mut Plugin) {
+  handle_termination();
+  info!("Running headless, stop with SIGTERM or Ctrl+C");
+  while !TERMINATE.load(Ordering::SeqCst) {
+    plugin.update();
+    thread::sleep(INTERVAL);
+  }
+  info!("Exiting...");
+  #[cfg(windows)]
+  FINISHED.store(true, Ordering::SeqCst);
+}
@@ -0,0 +1,341 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use log::debug;
+use serde_json::{json, Value};
+
+/// What made kicad-wakatime send a heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+  /// A document was focused, or the user came back to it after being idle.
+  Focus,
+  /// The focused document was saved.
+  Save,
+  /// KiCAD made a backup in which the document changed.
+  Backup,
+  /// The same document stayed focused while the user kept working.
+  KeepAlive,
+}
+
+impl fmt::Display for Trigger {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let trigger = match self {
+      Trigger::Focus => "focus",
+      Trigger::Save => "save",
+      Trigger::Backup => "backup",
+      Trigger::KeepAlive => "keep-alive",
+    };
+    write!(f, "{trigger}")
+  }
+}
+
+impl FromStr for Trigger {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "focus" => Ok(Trigger::Focus),
+      "save" => Ok(Trigger::Save),
+      "backup" => Ok(Trigger::Backup),
+      "keep-alive" => Ok(Trigger::KeepAlive),
+      _ => anyhow::bail!("trigger should be focus, save, backup or keep-alive, not {s:?}"),
+    }
+  }
+}
+
+/// What happened to a heartbeat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  /// The WakaTime CLI sent it to the server.
+  Sent,
+  /// The WakaTime CLI saved it to send later, e.g. because the server could not be reached.
+  Queued,
+  /// The heartbeat was lost, e.g. because of a bad API key.
+  Failed,
+}
+
+impl Outcome {
+  /// Return the outcome of a heartbeat from the exit code of the WakaTime CLI.
+  pub fn from_exit_code(code: Option<i32>) -> Self {
+    match code {
+      Some(0) => Outcome::Sent,
+      // API error and rate limiting, the CLI keeps the heartbeat in its offline queue
+      Some(102) | Some(112) => Outcome::Queued,
+      _ => Outcome::Failed,
+    }
+  }
+}
+
+impl fmt::Display for Outcome {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let outcome = match self {
+      Outcome::Sent => "sent",
+      Outcome::Queued => "queued",
+      Outcome::Failed => "failed",
+    };
+    write!(f, "{outcome}")
+  }
+}
+
+impl FromStr for Outcome {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "sent" => Ok(Outcome::Sent),
+      "queued" => Ok(Outcome::Queued),
+      "failed" => Ok(Outcome::Failed),
+      _ => anyhow::bail!("result should be sent, queued or failed, not {s:?}"),
+    }
+  }
+}
+
+/// A heartbeat kicad-wakatime tried to send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+  pub time: DateTime<Local>,
+  // the file the heartbeat was for
+  pub entity: PathBuf,
+  pub project: String,
+  // the KiCAD editor the file is edited in
+  pub editor: Option<String>,
+  pub category: Option<String>,
+  pub trigger: Trigger,
+  pub result: Outcome,
+}
+
+impl HistoryEntry {
+  pub fn to_json(&self) -> Value {
+    json!({
+      "time": self.time.to_rfc3339(),
+      "entity": self.entity,
+      "project": self.project,
+      "editor": self.editor,
+      "category": self.category,
+      "trigger": self.trigger.to_string(),
+      "result": self.result.to_string(),
+    })
+  }
+  pub fn from_json(value: &Value) -> Result<Self, anyhow::Error> {
+    let string = |key: &str| value[key].as_str().with_context(|| format!("missing {key:?}"));
+    Ok(HistoryEntry {
+      time: DateTime::parse_from_rfc3339(string("time")?)?.with_timezone(&Local),
+      entity: PathBuf::from(string("entity")?),
+      project: string("project")?.to_string(),
+      editor: value["editor"].as_str().map(str::to_string),
+      category: value["category"].as_str().map(str::to_string),
+      trigger: string("trigger")?.parse()?,
+      result: string("result")?.parse()?,
+    })
+  }
+}
+
+/// Which history entries to return. Every field that is set has to match.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+  pub since: Option<DateTime<Local>>,
+  pub until: Option<DateTime<Local>>,
+  pub entity: Option<PathBuf>,
+  pub project: Option<String>,
+  pub trigger: Option<Trigger>,
+  pub result: Option<Outcome>,
+  // only the newest this many entries
+  pub limit: Option<usize>,
+}
+
+impl Query {
+  pub fn matches(&self, entry: &HistoryEntry) -> bool {
+    self.since.is_none_or(|since| entry.time >= since) &&
+    self.until.is_none_or(|until| entry.time < until) &&
+    self.entity.as_ref().is_none_or(|entity| &entry.entity == entity) &&
+    self.project.as_ref().is_none_or(|project| &entry.project == project) &&
+    self.trigger.is_none_or(|trigger| entry.trigger == trigger) &&
+    self.result.is_none_or(|result| entry.result == result)
+  }
+}
+
+/// Every heartbeat kicad-wakatime tried to send, oldest first, kept in a file with one
+/// JSON object per line so that scripts can read it too. Entries are only ever appended,
+/// except by [`History::compact`].
+pub struct History {
+  pub path: PathBuf,
+}
+
+impl History {
+  pub fn new(path: &Path) -> Self {
+    History { path: path.to_path_buf() }
+  }
+  pub fn append(&self, entry: &HistoryEntry) -> Result<(), io::Error> {
+    if let Some(parent) = self.path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+    // one write per line, so that a line is never split by another writer
+    file.write_all(format!("{}\n", entry.to_json()).as_bytes())
+  }
+  /// Return the entries that match a query, oldest first.
+  /// Lines that can't be read (e.g. cut short by a crash) are skipped.
+  pub fn query(&self, query: &Query) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let mut entries: Vec<HistoryEntry> = self.entries()?
+      .into_iter()
+      .filter(|entry| query.matches(entry))
+      .collect();
+    if let Some(limit) = query.limit {
+      entries.drain(..entries.len().saturating_sub(limit));
+    }
+    Ok(entries)
+  }
+  /// Rewrite the history without the entries from before `keep_since` and without lines that
+  /// can't be read. Returns the number of lines removed.
+  pub fn compact(&self, keep_since: DateTime<Local>) -> Result<usize, anyhow::Error> {
+    if !self.path.is_file() {
+      return Ok(0)
+    }
+    let lines = BufReader::new(File::open(&self.path)?).lines().collect::<Result<Vec<_>, _>>()?;
+    let kept: Vec<&String> = lines.iter()
+      .filter(|line| parse_line(line).is_some_and(|entry| entry.time >= keep_since))
+      .collect();
+    let removed = lines.len() - kept.len();
+    if removed == 0 {
+      return Ok(0)
+    }
+    // write a new file and swap it in, so that the history is never half written
+    let mut temp_path = self.path.clone().into_os_string();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    let mut temp = BufWriter::new(File::create(&temp_path)?);
+    for line in kept {
+      writeln!(temp, "{line}")?;
+    }
+    temp.into_inner()?.sync_all()?;
+    fs::rename(&temp_path, &self.path)?;
+    Ok(removed)
+  }
+  fn entries(&self) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    if !self.path.is_file() {
+      return Ok(vec![])
+    }
+    let mut entries = vec![];
+    for line in BufReader::new(File::open(&self.path)?).lines() {
+      if let Some(entry) = parse_line(&line?) {
+        entries.push(entry);
+      }
+    }
+    Ok(entries)
+  }
+}
+
+fn parse_line(line: &str) -> Option<HistoryEntry> {
+  let entry = serde_json::from_str(line)
+    .map_err(anyhow::Error::from)
+    .and_then(|value| HistoryEntry::from_json(&value));
+  match entry {
+    Ok(entry) => Some(entry),
+    Err(e) => {
+      debug!("Skipping history line {line:?}: {e}");
+      None
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::Duration;
+
+  use super::*;
+
+  fn entry(now: DateTime<Local>, minutes_ago: i64, file: &str, trigger: Trigger, result: Outcome) -> HistoryEntry {
+    HistoryEntry {
+      time: now - Duration::minutes(minutes_ago),
+      entity: PathBuf::from(format!("/boards/main/{file}")),
+      project: String::from("main"),
+      editor: crate::editor_of(file).map(str::to_string),
+      category: None,
+      trigger,
+      result,
+    }
+  }
+
+  // whole seconds, since that is all the history keeps
+  fn now() -> DateTime<Local> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap().with_timezone(&Local)
+  }
+
+  #[test]
+  fn appends_and_queries() {
+    let temp = tempfile::tempdir().unwrap();
+    let history = History::new(&temp.path().join("state").join("history.jsonl"));
+    assert_eq!(history.query(&Query::default()).unwrap(), []);
+    let entries = [
+      entry(now(), 30, "main.kicad_sch", Trigger::Focus, Outcome::Sent),
+      entry(now(), 20, "main.kicad_pcb", Trigger::Save, Outcome::Queued),
+      entry(now(), 10, "main.kicad_pcb", Trigger::KeepAlive, Outcome::Failed),
+    ];
+    for entry in &entries {
+      history.append(entry).unwrap();
+    }
+    assert_eq!(history.query(&Query::default()).unwrap(), entries);
+    let pcb = Query { entity: Some(PathBuf::from("/boards/main/main.kicad_pcb")), ..Query::default() };
+    assert_eq!(history.query(&pcb).unwrap(), entries[1..]);
+    let failed = Query { result: Some(Outcome::Failed), ..Query::default() };
+    assert_eq!(history.query(&failed).unwrap(), entries[2..]);
+    let between = Query { since: Some(now() - Duration::minutes(20)), until: Some(now() - Duration::minutes(10)), ..Query::default() };
+    assert_eq!(history.query(&between).unwrap(), entries[1..2]);
+  }
+
+  #[test]
+  fn limit_keeps_newest() {
+    let temp = tempfile::tempdir().unwrap();
+    let history = History::new(&temp.path().join("history.jsonl"));
+    let entries: Vec<HistoryEntry> = (0..5).rev()
+      .map(|minutes_ago| entry(now(), minutes_ago, "main.kicad_pcb", Trigger::Focus, Outcome::Sent))
+      .collect();
+    for entry in &entries {
+      history.append(entry).unwrap();
+    }
+    let newest = history.query(&Query { limit: Some(2), ..Query::default() }).unwrap();
+    // the newest two, still oldest first
+    assert_eq!(newest, entries[3..]);
+    assert!(newest[0].time < newest[1].time);
+    let all = history.query(&Query { limit: Some(10), ..Query::default() }).unwrap();
+    assert_eq!(all, entries);
+  }
+
+  #[test]
+  fn skips_unreadable_lines() {
+    let temp = tempfile::tempdir().unwrap();
+    let history = History::new(&temp.path().join("history.jsonl"));
+    let first = entry(now(), 2, "main.kicad_sch", Trigger::Focus, Outcome::Sent);
+    let second = entry(now(), 1, "main.kicad_pcb", Trigger::Backup, Outcome::Sent);
+    let second_json = second.to_json().to_string();
+    fs::write(&history.path, format!(
+      "{}\nnot json\n{{\"time\": \"yesterday\"}}\n{}\n{}",
+      first.to_json(),
+      second_json,
+      // cut short by a crash
+      &second_json[..second_json.len() / 2],
+    )).unwrap();
+    assert_eq!(history.query(&Query::default()).unwrap(), [first, second]);
+  }
+
+  #[test]
+  fn compact_removes_old_entries() {
+    let temp = tempfile::tempdir().unwrap();
+    let history = History::new(&temp.path().join("history.jsonl"));
+    assert_eq!(history.compact(now()).unwrap(), 0);
+    let old = entry(now(), 60 * 24 * 91, "main.kicad_sch", Trigger::Focus, Outcome::Sent);
+    let recent = entry(now(), 60, "main.kicad_pcb", Trigger::Save, Outcome::Sent);
+    history.append(&old).unwrap();
+    fs::write(&history.path, format!("{}\ngarbage\n", fs::read_to_string(&history.path).unwrap().trim_end())).unwrap();
+    history.append(&recent).unwrap();
+    assert_eq!(history.compact(now() - Duration::days(90)).unwrap(), 2);
+    assert_eq!(history.query(&Query::default()).unwrap(), std::slice::from_ref(&recent));
+    assert_eq!(fs::read_to_string(&history.path).unwrap(), format!("{}\n", recent.to_json()));
+    // nothing left to remove
+    assert_eq!(history.compact(now() - Duration::days(90)).unwrap(), 0);
+  }
+}
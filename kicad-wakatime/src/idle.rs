@@ -0,0 +1,279 @@
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use mouse_position::mouse_position::Mouse;
+
+/// Time without activity after which heartbeats stop, unless set in the settings.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// how often the cursor and the system idle time are looked at
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tells how long the user has been away, from the system's idle time where it can be read
+/// (XScreenSaver on X11, ext-idle-notify on Wayland), otherwise from cursor movement.
+/// Saves and window title changes are reported with [`IdleDetector::activity`].
+pub struct IdleDetector {
+  #[cfg(target_os = "linux")]
+  system: Option<linux::SystemIdle>,
+  last_cursor: Option<(i32, i32)>,
+  last_activity: Instant,
+  last_poll: Option<Instant>,
+  idle_time: Duration,
+}
+
+impl Default for IdleDetector {
+  fn default() -> Self {
+    IdleDetector::new()
+  }
+}
+
+impl IdleDetector {
+  pub fn new() -> Self {
+    #[cfg(target_os = "linux")]
+    let system = linux::SystemIdle::connect();
+    let detector = IdleDetector {
+      #[cfg(target_os = "linux")]
+      system,
+      last_cursor: None,
+      last_activity: Instant::now(),
+      last_poll: None,
+      idle_time: Duration::ZERO,
+    };
+    info!("Idle time is read with {}", detector.backend());
+    detector
+  }
+  /// Return how idle time is found.
+  pub fn backend(&self) -> &'static str {
+    #[cfg(target_os = "linux")]
+    if let Some(ref system) = self.system {
+      return system.name()
+    }
+    "cursor movement"
+  }
+  /// Return how long there has been no activity.
+  pub fn poll(&mut self) -> Duration {
+    if self.last_poll.is_some_and(|t| t.elapsed() < POLL_INTERVAL) {
+      return self.idle_time
+    }
+    self.last_poll = Some(Instant::now());
+    #[cfg(target_os = "linux")]
+    if let Some(ref mut system) = self.system {
+      match system.idle_time() {
+        Some(idle_time) => {
+          self.idle_time = idle_time.min(self.last_activity.elapsed());
+          return self.idle_time
+        },
+        None => {
+          warn!("Could not read the idle time from {}, using the cursor instead", system.name());
+          self.system = None;
+        },
+      }
+    }
+    let cursor = cursor_position();
+    if cursor.is_some() && cursor != self.last_cursor {
+      if self.last_cursor.is_some() {
+        self.last_activity = Instant::now();
+      }
+      self.last_cursor = cursor;
+    }
+    self.idle_time = self.last_activity.elapsed();
+    self.idle_time
+  }
+  /// Note activity that the system does not see, e.g. a file being saved.
+  pub fn activity(&mut self) {
+    self.last_activity = Instant::now();
+    self.idle_time = Duration::ZERO;
+  }
+}
+
+/// Return the position of the cursor, if it can be read.
+fn cursor_position() -> Option<(i32, i32)> {
+  // mouse_position needs an X display on Linux, and panics or crashes without one
+  #[cfg(target_os = "linux")]
+  std::env::var_os("DISPLAY")?;
+  match Mouse::get_mouse_position() {
+    Mouse::Position { x, y } => Some((x, y)),
+    Mouse::Error => None,
+  }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+  use std::env;
+  use std::ptr;
+  use std::sync::{Arc, Mutex};
+  use std::thread::{self, JoinHandle};
+  use std::time::{Duration, Instant};
+  use log::warn;
+  use wayland_client::globals::{registry_queue_init, GlobalListContents};
+  use wayland_client::protocol::{wl_registry, wl_seat};
+  use wayland_client::{delegate_noop, Connection, Dispatch, QueueHandle};
+  use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notification_v1::{self, ExtIdleNotificationV1};
+  use wayland_protocols::ext::idle_notify::v1::client::ext_idle_notifier_v1::ExtIdleNotifierV1;
+  use x11_dl::{xlib, xss};
+
+  // the compositor reports the user as idle after this long without input
+  const WAYLAND_IDLE_AFTER: Duration = Duration::from_secs(1);
+
+  pub enum SystemIdle {
+    X11(Box<X11Idle>),
+    Wayland(WaylandIdle),
+  }
+
+  impl SystemIdle {
+    /// Connect to whatever can tell the idle time in this session.
+    pub fn connect() -> Option<Self> {
+      if env::var_os("WAYLAND_DISPLAY").is_some() {
+        if let Some(wayland) = WaylandIdle::connect() {
+          return Some(SystemIdle::Wayland(wayland))
+        }
+      }
+      // on Wayland, XScreenSaver only sees input to XWayland windows
+      if env::var_os("DISPLAY").is_some() && env::var_os("WAYLAND_DISPLAY").is_none() {
+        if let Some(x11) = X11Idle::connect() {
+          return Some(SystemIdle::X11(Box::new(x11)))
+        }
+      }
+      None
+    }
+    pub fn name(&self) -> &'static str {
+      match self {
+        SystemIdle::X11(_) => "XScreenSaver",
+        SystemIdle::Wayland(_) => "ext-idle-notify",
+      }
+    }
+    pub fn idle_time(&mut self) -> Option<Duration> {
+      match self {
+        SystemIdle::X11(x11) => x11.idle_time(),
+        SystemIdle::Wayland(wayland) => wayland.idle_time(),
+      }
+    }
+  }
+
+  /// Idle time from the X11 screen saver extension, which counts keyboard and mouse input.
+  pub struct X11Idle {
+    xlib: xlib::Xlib,
+    xss: xss::Xss,
+    display: *mut xlib::Display,
+    info: *mut xss::XScreenSaverInfo,
+  }
+
+  impl X11Idle {
+    fn connect() -> Option<Self> {
+      // libX11 and libXss are loaded at runtime, so they don't have to be installed
+      let xlib = xlib::Xlib::open().ok()?;
+      let xss = xss::Xss::open().ok()?;
+      let display = unsafe { (xlib.XOpenDisplay)(ptr::null()) };
+      if display.is_null() {
+        return None
+      }
+      let (mut event_base, mut error_base) = (0, 0);
+      if unsafe { (xss.XScreenSaverQueryExtension)(display, &mut event_base, &mut error_base) } == 0 {
+        unsafe { (xlib.XCloseDisplay)(display) };
+        return None
+      }
+      let info = unsafe { (xss.XScreenSaverAllocInfo)() };
+      if info.is_null() {
+        unsafe { (xlib.XCloseDisplay)(display) };
+        return None
+      }
+      Some(X11Idle { xlib, xss, display, info })
+    }
+    fn idle_time(&mut self) -> Option<Duration> {
+      unsafe {
+        let root = (self.xlib.XDefaultRootWindow)(self.display);
+        if (self.xss.XScreenSaverQueryInfo)(self.display, root, self.info) == 0 {
+          return None
+        }
+        // c_ulong is only 32 bits on some targets
+        #[allow(clippy::useless_conversion)]
+        Some(Duration::from_millis(u64::from((*self.info).idle)))
+      }
+    }
+  }
+
+  impl Drop for X11Idle {
+    fn drop(&mut self) {
+      unsafe {
+        (self.xlib.XFree)(self.info.cast());
+        (self.xlib.XCloseDisplay)(self.display);
+      }
+    }
+  }
+
+  /// Idle time from the Wayland compositor's ext-idle-notify protocol.
+  /// The compositor's events are handled on a thread of their own.
+  pub struct WaylandIdle {
+    // when the compositor said the user went idle, or None while they are active
+    idle_since: Arc<Mutex<Option<Instant>>>,
+    // ends when the connection to the compositor is lost
+    thread: JoinHandle<()>,
+  }
+
+  struct State {
+    idle_since: Arc<Mutex<Option<Instant>>>,
+  }
+
+  impl WaylandIdle {
+    fn connect() -> Option<Self> {
+      let connection = Connection::connect_to_env().ok()?;
+      let (globals, mut queue) = registry_queue_init::<State>(&connection).ok()?;
+      let qh = queue.handle();
+      let seat: wl_seat::WlSeat = globals.bind(&qh, 1..=1, ()).ok()?;
+      let notifier: ExtIdleNotifierV1 = globals.bind(&qh, 1..=1, ()).ok()?;
+      notifier.get_idle_notification(WAYLAND_IDLE_AFTER.as_millis() as u32, &seat, &qh, ());
+      let idle_since = Arc::new(Mutex::new(None));
+      let mut state = State { idle_since: idle_since.clone() };
+      let thread = thread::spawn(move || {
+        // keep the connection and the notifier alive for as long as the thread runs
+        let _connection = connection;
+        let _notifier = notifier;
+        loop {
+          if let Err(e) = queue.blocking_dispatch(&mut state) {
+            warn!("Lost the connection to the Wayland compositor for the idle time: {e}");
+            break;
+          }
+        }
+      });
+      Some(WaylandIdle { idle_since, thread })
+    }
+    fn idle_time(&self) -> Option<Duration> {
+      // without the thread, the compositor's notifications are no longer seen
+      if self.thread.is_finished() {
+        return None
+      }
+      let idle_since = *self.idle_since.lock().ok()?;
+      Some(idle_since.map(|t| t.elapsed() + WAYLAND_IDLE_AFTER).unwrap_or_default())
+    }
+  }
+
+  impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
+    fn event(
+      _: &mut Self,
+      _: &wl_registry::WlRegistry,
+      _: wl_registry::Event,
+      _: &GlobalListContents,
+      _: &Connection,
+      _: &QueueHandle<Self>,
+    ) {}
+  }
+
+  impl Dispatch<ExtIdleNotificationV1, ()> for State {
+    fn event(
+      state: &mut Self,
+      _: &ExtIdleNotificationV1,
+      event: ext_idle_notification_v1::Event,
+      _: &(),
+      _: &Connection,
+      _: &QueueHandle<Self>,
+    ) {
+      let Ok(mut idle_since) = state.idle_since.lock() else { return };
+      match event {
+        ext_idle_notification_v1::Event::Idled => *idle_since = Some(Instant::now()),
+        ext_idle_notification_v1::Event::Resumed => *idle_since = None,
+        _ => {},
+      }
+    }
+  }
+
+  delegate_noop!(State: ignore wl_seat::WlSeat);
+  delegate_noop!(State: ExtIdleNotifierV1);
+}
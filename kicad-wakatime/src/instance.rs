@@ -0,0 +1,38 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+
+use crate::paths;
+
+/// A lock held for as long as kicad-wakatime runs, so that a second start can tell
+/// there is already a kicad-wakatime tracking, instead of tracking everything twice.
+/// The lock file holds the process ID of the kicad-wakatime that has it.
+pub struct InstanceLock {
+  // the lock is released when the file is closed, including when the process dies
+  _file: File,
+}
+
+impl InstanceLock {
+  /// Take the lock, or return `None` if another kicad-wakatime has it.
+  pub fn acquire() -> Result<Option<Self>, io::Error> {
+    fs::create_dir_all(paths::state_dir())?;
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(paths::lock_path())?;
+    match file.try_lock() {
+      Ok(()) => {},
+      Err(TryLockError::WouldBlock) => return Ok(None),
+      Err(TryLockError::Error(e)) => return Err(e),
+    }
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    Ok(Some(InstanceLock { _file: file }))
+  }
+}
+
+/// Return the process ID of the running kicad-wakatime, as written to the lock file.
+pub fn running_pid() -> Option<u32> {
+  fs::read_to_string(paths::lock_path()).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,199 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use chrono::Local;
+use log::info;
+use serde_json::Value;
+
+/// Autosave interval (in seconds) that kicad-wakatime needs.
+pub const RECOMMENDED_AUTOSAVE_INTERVAL: u64 = 60;
+
+/// A setting in kicad_common.json that kicad-wakatime cares about.
+struct Requirement {
+  /// JSON pointer to the setting.
+  pointer: &'static str,
+  /// What the user should change, as shown in KiCAD's preferences.
+  description: &'static str,
+  is_ok: fn(&Value) -> bool,
+  recommended: fn() -> Value,
+}
+
+const REQUIREMENTS: &[Requirement] = &[
+  Requirement {
+    pointer: "/system/autosave_interval",
+    description: "Auto save should be set to 1 minute",
+    is_ok: |v| v.as_u64().is_some_and(|i| i > 0 && i <= RECOMMENDED_AUTOSAVE_INTERVAL),
+    recommended: || Value::from(RECOMMENDED_AUTOSAVE_INTERVAL),
+  },
+  Requirement {
+    pointer: "/backup/enabled",
+    description: "\"Automatically backup projects\" should be checked",
+    is_ok: |v| v.as_bool() == Some(true),
+    recommended: || Value::Bool(true),
+  },
+  Requirement {
+    pointer: "/backup/backup_on_autosave",
+    description: "\"Create backups when auto save occurs\" should be checked",
+    is_ok: |v| v.as_bool() == Some(true),
+    recommended: || Value::Bool(true),
+  },
+  Requirement {
+    pointer: "/backup/min_interval",
+    description: "Minimum time between backups should be set to 0 minutes",
+    is_ok: |v| v.as_u64() == Some(0),
+    recommended: || Value::from(0),
+  },
+];
+
+/// KiCAD's kicad_common.json, which holds the autosave and backup settings.
+pub struct KicadCommon {
+  pub path: PathBuf,
+  pub json: Value,
+}
+
+impl KicadCommon {
+  /// Load the kicad_common.json of the newest KiCAD version installed, if any.
+  pub fn load() -> Result<Option<Self>, anyhow::Error> {
+    let Some(path) = kicad_config_dirs()
+      .into_iter()
+      .map(|dir| dir.join("kicad_common.json"))
+      .find(|path| path.is_file()) else {
+      return Ok(None)
+    };
+    let json = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    Ok(Some(KicadCommon { path, json }))
+  }
+  /// Return a description of every setting that does not match what kicad-wakatime needs.
+  pub fn problems(&self) -> Vec<String> {
+    REQUIREMENTS.iter()
+      .filter(|r| !self.json.pointer(r.pointer).is_some_and(r.is_ok))
+      .map(|r| r.description.to_string())
+      .collect()
+  }
+  /// Return the backup folder configured in KiCAD, if it is not the default `<project>-backups`.
+  /// A relative path is relative to the project folder.
+  pub fn backup_path(&self) -> Option<PathBuf> {
+    self.json.pointer("/backup/path")
+      .and_then(Value::as_str)
+      .filter(|path| !path.is_empty())
+      .map(PathBuf::from)
+  }
+  /// Back up kicad_common.json, then rewrite it with the recommended settings.
+  /// Return the path to the backup.
+  pub fn apply_recommended(&mut self) -> Result<PathBuf, anyhow::Error> {
+    let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+    let backup_path = self.path.with_extension(format!("json.{timestamp}.bak"));
+    fs::copy(&self.path, &backup_path)?;
+    info!("Backed up {:?} to {:?}", self.path, backup_path);
+    for requirement in REQUIREMENTS {
+      set_pointer(&mut self.json, requirement.pointer, (requirement.recommended)());
+    }
+    fs::write(&self.path, serde_json::to_string_pretty(&self.json)?)?;
+    Ok(backup_path)
+  }
+}
+
+/// KiCAD's project manager settings (kicad.json), which remember the open and recent projects.
+pub struct KicadProjectManager {
+  pub path: PathBuf,
+  pub json: Value,
+}
+
+impl KicadProjectManager {
+  /// Return the path to kicad.json of the newest KiCAD version installed, if any.
+  pub fn find() -> Option<PathBuf> {
+    kicad_config_dirs()
+      .into_iter()
+      .map(|dir| dir.join("kicad.json"))
+      .find(|path| path.is_file())
+  }
+  pub fn load(path: PathBuf) -> Result<Self, anyhow::Error> {
+    let json = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    Ok(KicadProjectManager { path, json })
+  }
+  /// Return the .kicad_pro files at a JSON pointer that still exist.
+  fn projects_at(&self, pointer: &str) -> Vec<PathBuf> {
+    self.json.pointer(pointer)
+      .and_then(Value::as_array)
+      .into_iter()
+      .flatten()
+      .filter_map(Value::as_str)
+      .map(PathBuf::from)
+      .filter(|path| path.extension().is_some_and(|e| e == "kicad_pro") && path.is_file())
+      .collect()
+  }
+  /// Return the projects open in KiCAD.
+  pub fn open_projects(&self) -> Vec<PathBuf> {
+    self.projects_at("/system/open_projects")
+  }
+  /// Return the projects KiCAD opened recently, newest first.
+  pub fn recent_projects(&self) -> Vec<PathBuf> {
+    self.projects_at("/system/file_history")
+  }
+}
+
+/// Set the value at a JSON pointer, creating intermediate objects as needed.
+fn set_pointer(json: &mut Value, pointer: &str, value: Value) {
+  let mut target = json;
+  for key in pointer.trim_start_matches('/').split('/') {
+    if !target.is_object() {
+      *target = Value::Object(Default::default());
+    }
+    target = target.as_object_mut().unwrap()
+      .entry(key)
+      .or_insert(Value::Null);
+  }
+  *target = value;
+}
+
+/// Return the folders KiCAD may keep its settings in, without version subfolders.
+/// This covers KICAD_CONFIG_HOME, the usual per-OS location and the Flatpak sandbox.
+pub fn kicad_config_bases() -> Vec<PathBuf> {
+  let mut bases = vec![];
+  if let Some(config_home) = env::var_os("KICAD_CONFIG_HOME") {
+    bases.push(PathBuf::from(config_home));
+  }
+  let Some(home_dir) = home::home_dir() else { return bases };
+  match std::env::consts::OS {
+    "macos" => bases.push(home_dir.join("Library/Preferences/kicad")),
+    "windows" => {
+      if let Some(app_data) = env::var_os("APPDATA") {
+        bases.push(PathBuf::from(app_data).join("kicad"));
+      }
+    },
+    _ => {
+      let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir.join(".config"));
+      bases.push(config_dir.join("kicad"));
+      bases.push(home_dir.join(".var/app/org.kicad.KiCad/config/kicad"));
+    },
+  }
+  bases
+}
+
+/// Return every versioned KiCAD settings folder (e.g. `~/.config/kicad/9.0`), newest version first.
+pub fn kicad_config_dirs() -> Vec<PathBuf> {
+  let mut dirs = vec![];
+  for base in kicad_config_bases() {
+    let Ok(entries) = fs::read_dir(&base) else { continue };
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let Some(version) = path.file_name().and_then(|n| n.to_str()).and_then(parse_version) else {
+        continue
+      };
+      if path.is_dir() {
+        dirs.push((version, path));
+      }
+    }
+  }
+  // stable sort, so KICAD_CONFIG_HOME wins over other folders with the same version
+  dirs.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+  dirs.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Parse a settings folder name like `8.0` or `8.99` into (major, minor).
+fn parse_version(name: &str) -> Option<(u32, u32)> {
+  let (major, minor) = name.split_once('.')?;
+  Some((major.parse().ok()?, minor.parse().ok()?))
+}
@@ -1,13 +1,15 @@
 use core::str;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::env;
-use std::fs::{self, File};
-use std::io::{Cursor, Read, Write};
-use std::path::PathBuf;
-use std::process::Command;
-use std::sync::mpsc::{Receiver, Sender};
+use std::fs;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use active_win_pos_rs::{get_active_window, ActiveWindow};
+use anyhow::Context;
 use chrono::{DateTime, Local};
 use ini::Ini;
 use log::debug;
@@ -15,46 +17,123 @@ use log::info;
 use log::error;
 use log::warn;
 use regex::Regex;
-use notify::{Watcher, RecommendedWatcher, RecursiveMode};
-use zip::ZipArchive;
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
+use backups::Change;
+use cli_config::CliConfig;
+use config::{Config, ConfigOverrides, Validation};
+use connection::ConnectionTest;
+use control::ControlServer;
+use history::{History, HistoryEntry, Outcome, Trigger};
+use idle::IdleDetector;
+use kicad_settings::{KicadCommon, KicadProjectManager};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use pause::Pause;
+use project::Project;
+use project_config::PROJECT_CONFIG_NAME;
+use prune::PruneMode;
 
+pub mod backups;
+pub mod cli_config;
+pub mod config;
+pub mod connection;
+pub mod control;
+pub mod crash;
+pub mod diagnostics;
+pub mod doctor;
+pub mod headless;
+pub mod history;
+pub mod idle;
+pub mod instance;
+pub mod kicad_settings;
+pub mod log_file;
+pub mod pause;
+pub mod paths;
+pub mod project;
+pub mod project_config;
+pub mod prune;
+pub mod scrub;
 pub mod ui;
 
 const PLUGIN_VERSION: &str = env!("CARGO_PKG_VERSION");
+// number of window titles kept for diagnostics
+const TITLE_TRACE_LENGTH: usize = 50;
+// heartbeats older than this are removed from the history when kicad-wakatime starts
+const HISTORY_KEEP_DAYS: i64 = 90;
 
 pub struct Plugin {
   pub version: &'static str,
   pub disable_heartbeats: bool,
   pub redownload: bool,
+  // heartbeats are not sent while paused
+  pub pause: Option<Pause>,
+  pub idle: IdleDetector,
+  // heartbeats are not sent while the user is away
+  pub is_idle: bool,
+  // set when the user comes back, so that a heartbeat is sent as soon as a KiCAD document is focused
+  pub resumed_from_idle: bool,
+  pub config: Config,
+  // settings as they are in the config files, without overrides
+  pub file_config: Config,
+  pub overrides: ConfigOverrides,
+  // settings as edited in the settings window, applied when OK is clicked
+  pub config_draft: Config,
+  pub config_validation: Validation,
+  pub draft_validation: Validation,
+  // quiet hours being typed in the settings window
+  pub quiet_hours_draft: String,
+  // API key and URL test started from the settings window
+  pub connection_test: Option<ConnectionTest>,
+  // the config files as loaded, so that keys kicad-wakatime doesn't know about are kept
   pub wakatime_config: Ini,
   pub kicad_wakatime_config: Ini,
+  // private copy of .wakatime.cfg for the WakaTime CLI, when the API key or URL is overridden
+  pub cli_config: Option<CliConfig>,
   pub settings_open: bool,
   pub tx: Option<Sender<notify::Result<notify::Event>>>,
   pub rx: Option<Receiver<notify::Result<notify::Event>>>,
   // filename of currently focused file
   pub filename: String,
-  pub symbol: String,
-  pub footprint: String,
   pub warned: String,
   pub warned_kicad: String,
   // path of currently focused file
   pub full_path: PathBuf,
-  pub full_paths: HashMap<String, PathBuf>,
-  pub file_watcher: Option<RecommendedWatcher>,
-  pub projects_folder: String,
-  pub projects_file: String,
-  pub api_key: String,
-  pub api_url: String,
+  // project name in the title of the focused window
+  pub focused_project: String,
+  // the most recent window titles, newest last, for diagnostics
+  pub title_trace: VecDeque<(DateTime<Local>, String)>,
+  pub projects: Vec<Project>,
+  // projects KiCAD has open (or opened recently) that are not in the settings
+  pub suggested_projects: Vec<PathBuf>,
+  pub kicad_json_path: Option<PathBuf>,
+  pub kicad_json_watcher: Option<RecommendedWatcher>,
+  // watches .wakatime.cfg and kicad-wakatime.cfg, which other programs may change
+  pub config_watcher: Option<RecommendedWatcher>,
   pub time: Duration,
   // the last time a heartbeat was sent
   pub last_sent_time: Duration,
   pub last_sent_time_chrono: Option<DateTime<Local>>,
   // the last file that was sent
   pub last_sent_file: String,
+  // when each file last got a heartbeat, including files that weren't focused
+  pub last_sent_times: HashMap<PathBuf, Duration>,
+  // heartbeats the WakaTime CLI is sending in the background, with how it went once it's done
+  pub heartbeats_tx: Sender<(HistoryEntry, io::Result<Output>)>,
+  pub heartbeats_rx: Receiver<(HistoryEntry, io::Result<Output>)>,
+  // the last time a keep-alive heartbeat was tried, sent or not
+  pub last_keep_alive_time: Duration,
+  // every heartbeat that was tried, kept on disk
+  pub history: History,
   pub has_screen_capture_access: bool,
   pub first_iteration_finished: bool,
+  pub control_server: Option<ControlServer>,
+  // set when another start of kicad-wakatime asks for the window to be brought to the front
+  pub focus_requested: bool,
+  pub kicad_common: Option<KicadCommon>,
+  // autosave/backup settings in kicad_common.json that need changing
+  pub kicad_settings_problems: Vec<String>,
+  // details of the last crash, until they are dismissed
+  pub last_crash: Option<String>,
 }
 
 impl Plugin {
@@ -62,85 +141,130 @@ impl Plugin {
     disable_heartbeats: bool,
     redownload: bool,
   ) -> Self {
+    let (heartbeats_tx, heartbeats_rx) = mpsc::channel();
     Plugin {
       version: PLUGIN_VERSION,
       disable_heartbeats,
       redownload,
+      pause: None,
+      idle: IdleDetector::new(),
+      is_idle: false,
+      resumed_from_idle: false,
+      config: Config::default(),
+      file_config: Config::default(),
+      overrides: ConfigOverrides::default(),
+      config_draft: Config::default(),
+      config_validation: Validation::default(),
+      draft_validation: Validation::default(),
+      quiet_hours_draft: String::default(),
+      connection_test: None,
       wakatime_config: Ini::default(),
       kicad_wakatime_config: Ini::default(),
+      cli_config: None,
       settings_open: false,
       tx: None,
       rx: None,
       filename: String::default(),
-      symbol: String::default(),
-      footprint: String::default(),
       warned: String::default(),
       warned_kicad: String::default(),
       full_path: PathBuf::default(),
-      full_paths: HashMap::default(),
-      file_watcher: None,
-      projects_folder: String::default(),
-      projects_file: String::default(),
-      api_key: String::default(),
-      api_url: String::default(),
+      focused_project: String::default(),
+      title_trace: VecDeque::new(),
+      projects: vec![],
+      suggested_projects: vec![],
+      kicad_json_path: None,
+      kicad_json_watcher: None,
+      config_watcher: None,
       time: Duration::default(),
       last_sent_time: Duration::default(),
       last_sent_time_chrono: None,
       last_sent_file: String::default(),
+      last_sent_times: HashMap::new(),
+      heartbeats_tx,
+      heartbeats_rx,
+      last_keep_alive_time: Duration::default(),
+      history: History::new(&paths::history_path()),
       has_screen_capture_access: true,
       first_iteration_finished: false,
+      control_server: None,
+      focus_requested: false,
+      kicad_common: None,
+      kicad_settings_problems: vec![],
+      last_crash: None,
+    }
+  }
+  /// Do one round of tracking: look at the focused window and handle file changes.
+  /// Errors are logged rather than returned, so that tracking keeps going.
+  pub fn update(&mut self) {
+    if let Err(e) = self.main_loop() {
+      error!("{:?}", e);
+      self.first_iteration_finished = true;
+    }
+    if let Err(e) = self.try_recv() {
+      error!("{:?}", e);
+    }
+    self.check_heartbeats();
+    self.handle_control_requests();
+  }
+  /// Return a short description of what kicad-wakatime is doing, as shown in the status line.
+  pub fn status(&self) -> &'static str {
+    if !self.first_iteration_finished {
+      "loading..."
+    } else if !self.config_validation.problems(&self.config).is_empty() {
+      "need settings!"
+    } else if self.pause.is_some() {
+      "paused"
+    } else if self.in_quiet_hours() {
+      "quiet hours"
+    } else if self.is_idle {
+      "idle"
+    } else {
+      "OK"
     }
   }
   pub fn main_loop(&mut self) -> Result<(), anyhow::Error> {
     if !self.first_iteration_finished {
-      self.check_up_to_date()?;
-      self.check_cli_installed(self.redownload)?;
-      let projects_folder = if self.projects_file == "" {
-          "".to_string()
-      } else { 
-          self.get_projects_file().parent().expect("Uh os problem").to_str().unwrap().to_string()
-      };
-      self.watch_files(projects_folder.into())?;
+      // none of these stop tracking when they fail, so that the projects are still watched
+      // (e.g. when GitHub can't be reached, or in headless mode with nobody to retry)
+      self.start_control_server();
+      if let Err(e) = self.load_pause() {
+        error!("Could not load the pause from before the restart: {e:#}");
+      }
+      self.compact_history();
+      if let Err(e) = self.check_up_to_date() {
+        error!("Could not check for kicad-wakatime updates: {e:#}");
+      }
+      if let Err(e) = self.check_cli_installed(self.redownload) {
+        error!("Could not check the WakaTime CLI: {e:#}");
+      }
+      if let Err(e) = self.check_kicad_settings() {
+        error!("Could not check KiCAD's settings: {e:#}");
+      }
+      if let Err(e) = self.watch_config() {
+        error!("Could not watch the config files: {e:#}");
+      }
+      if let Err(e) = self.watch_projects() {
+        error!("Could not watch the projects: {e:#}");
+      }
+      if let Err(e) = self.watch_kicad_projects() {
+        error!("Could not watch KiCAD's open projects: {e:#}");
+      }
       info!("Finished setting up");
     }
 
     self.set_current_time(self.current_time());
+    self.check_idle();
+    self.check_pause()?;
 
-    // Hyprland sets this environment variable
-    let hypr_signature = env::var("HYPRLAND_INSTANCE_SIGNATURE");
-
-    let title: String = if hypr_signature.is_ok() {
-      // We are on hyyyyyyyyyperland! I don't want to keep looking at low res windows
-      let command = Command::new("hyprctl")
-                            .arg("activewindow")
-                            .output()
-                            .expect("`hyprctl` should be executable on Hyprland")
-                            .stdout;
-      let result = str::from_utf8(&command).unwrap();
-
-      let re = Regex::new(r"title: (?<title>[\S ]*)\n").unwrap();
-      match re.captures(result) {
-        Some(caps) => caps["title"].to_string(),
-        _ => { // Something went wrong with hyprctl I guess
-          warn!("Couldn't get title via hyprland, falling back"); 
-
-          let Ok(w) = self.get_active_window() else {
-            self.first_iteration_finished = true;
-            return Ok(());
-          };
-
-          w.title
-        }
-      }
-    } else {
-      // Use the normal method
-      let Ok(w) = self.get_active_window() else {
-        self.first_iteration_finished = true;
-        return Ok(());
-      };
-
-      w.title
+    let Some(title) = self.active_window_title() else {
+      self.first_iteration_finished = true;
+      return Ok(());
     };
+    // switching windows counts as activity, even where input can't be seen
+    if self.title_trace.back().is_some_and(|(_, last)| *last != title) {
+      self.activity();
+    }
+    self.trace_title(&title);
 
     if title == "Pin Properties" {
         // TODO
@@ -170,9 +294,12 @@ impl Plugin {
         error!("Can't find [ in project {}! Skipping", project);
         return Ok(());
     }
+    self.focused_project = project.to_string();
 
-    let symbol_dir = &self.symbol;
-    let footprint_dir = &self.footprint;
+    let symbol_dir = self.find_symbol_file(project).unwrap_or_default();
+    let symbol_dir = symbol_dir.to_string_lossy();
+    let footprint_dir = self.find_footprint_folder(project).unwrap_or_default();
+    let footprint_dir = footprint_dir.to_string_lossy();
     let mut filename = match editor {
       "Schematic Editor" => format!("{project}.kicad_sch"),
       "PCB Editor" => format!("{project}.kicad_pcb"),
@@ -187,23 +314,25 @@ impl Plugin {
         filename = format!("{name}.kicad_sch");
     }
 
-    if (editor != "Symbol Editor" && editor != "Footprint Editor") || filename == "" {
+    if (editor != "Symbol Editor" && editor != "Footprint Editor") || filename.is_empty() {
       let Some(_full_path) = self.get_full_path(filename.clone()) else {
-        if filename == "" {
+        if filename.is_empty() {
           match editor {
               "Symbol Editor" => warn!("Symbol file path empty, did you forget to set it?"),
               "Footprint Editor" => warn!("Footprint directory path empty, did you forget to set it?"),
               _ => {
                   if self.warned_kicad != filename {
-                    warn!("Can't find {} in {}, did you choose the wrong project?", filename, self.projects_folder);
+                    warn!("Can't find {} in any tracked project, did you choose the wrong project?", filename);
                     self.warned_kicad = filename;
+                    self.discover_projects()?;
                   }
               },
           }
         } else {
           if self.warned_kicad != filename {
-            warn!("Can't find {} in {}, did you choose the wrong project?", filename, self.projects_folder);
+            warn!("Can't find {} in any tracked project, did you choose the wrong project?", filename);
             self.warned_kicad = filename;
+            self.discover_projects()?;
           }
         }
 
@@ -211,24 +340,115 @@ impl Plugin {
         return Ok(());
       };
     }
-    // let project_folder = full_path.parent().unwrap().to_path_buf();
-    // let backups_folder = project_folder.join(format!("{project}-backups"));
     self.set_current_file(filename.clone())?;
-    // self.look_at_backups_of_filename(filename, backups_folder);
+    self.keep_alive(filename)?;
     self.first_iteration_finished = true;
     Ok(())
   }
+  /// Check KiCAD's autosave and backup settings against what kicad-wakatime needs.
+  pub fn check_kicad_settings(&mut self) -> Result<(), anyhow::Error> {
+    self.kicad_common = KicadCommon::load()?;
+    let Some(ref kicad_common) = self.kicad_common else {
+      warn!("Could not find kicad_common.json, unable to check KiCAD's backup settings");
+      self.kicad_settings_problems = vec![];
+      return Ok(())
+    };
+    info!("KiCAD settings: {:?}", kicad_common.path);
+    self.kicad_settings_problems = kicad_common.problems();
+    for problem in &self.kicad_settings_problems {
+      warn!("KiCAD settings: {problem}");
+    }
+    Ok(())
+  }
+  /// Back up kicad_common.json and apply the recommended autosave and backup settings.
+  pub fn apply_recommended_kicad_settings(&mut self) -> Result<(), anyhow::Error> {
+    let Some(ref mut kicad_common) = self.kicad_common else { return Ok(()) };
+    let backup_path = kicad_common.apply_recommended()?;
+    info!("Applied recommended KiCAD settings (original saved to {:?})", backup_path);
+    warn!("Restart KiCAD for the new settings to take effect");
+    self.kicad_settings_problems = kicad_common.problems();
+    Ok(())
+  }
+  /// Find out whether the user has gone idle or come back.
+  pub fn check_idle(&mut self) {
+    let idle_time = self.idle.poll();
+    let is_idle = self.config.idle_timeout().is_some_and(|timeout| idle_time >= timeout);
+    if is_idle && !self.is_idle {
+      info!("Idle for {}s, not sending heartbeats", idle_time.as_secs());
+      self.is_idle = true;
+    } else if !is_idle && self.is_idle {
+      self.back_from_idle();
+    }
+  }
+  /// Note activity that the idle detector can't see, such as a save or a window switch.
+  pub fn activity(&mut self) {
+    self.idle.activity();
+    if self.is_idle {
+      self.back_from_idle();
+    }
+  }
+  fn back_from_idle(&mut self) {
+    info!("No longer idle");
+    self.is_idle = false;
+    self.resumed_from_idle = true;
+  }
+  /// Remember a window title if it is different from the last one.
+  pub fn trace_title(&mut self, title: &str) {
+    if self.title_trace.back().is_some_and(|(_, last)| last == title) {
+      return
+    }
+    if self.title_trace.len() == TITLE_TRACE_LENGTH {
+      self.title_trace.pop_front();
+    }
+    self.title_trace.push_back((Local::now(), title.to_string()));
+  }
+  /// Forget the last crash, hiding the banner about it.
+  pub fn dismiss_crash(&mut self) -> Result<(), anyhow::Error> {
+    self.last_crash = None;
+    crash::dismiss()?;
+    Ok(())
+  }
+  /// Return how the title of the focused window is found.
+  pub fn window_backend(&self) -> &'static str {
+    // Hyprland sets this environment variable
+    if env::var("HYPRLAND_INSTANCE_SIGNATURE").is_ok() {
+      "hyprctl"
+    } else {
+      "active-win-pos-rs"
+    }
+  }
+  /// Return the title of the focused window, if it can be found.
+  pub fn active_window_title(&mut self) -> Option<String> {
+    if self.window_backend() == "hyprctl" {
+      // We are on hyyyyyyyyyperland! I don't want to keep looking at low res windows
+      let command = Command::new("hyprctl")
+                            .arg("activewindow")
+                            .output()
+                            .map(|output| output.stdout)
+                            .unwrap_or_default();
+      let result = String::from_utf8_lossy(&command);
+
+      let re = Regex::new(r"title: (?<title>[\S ]*)\n").unwrap();
+      match re.captures(&result) {
+        Some(caps) => return Some(caps["title"].to_string()),
+        // Something went wrong with hyprctl I guess
+        _ => warn!("Couldn't get title via hyprland, falling back"),
+      }
+    }
+    // Use the normal method
+    self.get_active_window().ok().map(|w| w.title)
+  }
+  #[allow(clippy::result_unit_err)]
   pub fn get_active_window(&mut self) -> Result<ActiveWindow, ()> {
     let active_window = get_active_window();
     // as far as i can tell, active_win_pos_rs will focus on kicad-wakatime
     // when it starts, and that window should by all means have a title.
     // if the field is empty, kicad-wakatime is missing permissions
-    if !self.has_screen_capture_access {
-      if active_window.clone().is_ok_and(|w| w.app_name == "kicad-wakatime" && w.title.is_empty()) {
-        error!("Could not get title of active window!");
-        error!("If you are on macOS, please give kicad-wakatime Screen Recording permission");
-        error!("(System Settings -> Privacy and Security -> Screen Recording)");
-      }
+    if !self.has_screen_capture_access &&
+    active_window.clone().is_ok_and(|w| w.app_name == "kicad-wakatime" && w.title.is_empty()) {
+      error!("Could not get title of active window!");
+      error!("If you are on macOS, please give kicad-wakatime Screen Recording permission");
+      error!("(System Settings -> Privacy and Security -> Screen Recording)");
     }
     active_window
   }
@@ -243,7 +463,7 @@ impl Plugin {
         cli.creation_flags(0x08000000); // CREATE_NO_WINDOW
       }
       let cli_output = cli.output()
-        .expect("Could not execute WakaTime CLI!");
+        .context("Could not execute WakaTime CLI!")?;
       let cli_stdout = cli_output.stdout;
       let cli_stdout = std::str::from_utf8(&cli_stdout)?;
       // TODO: update to latest version if needed
@@ -262,13 +482,13 @@ impl Plugin {
     let client = reqwest::blocking::Client::new();
     // need to insert some kind of user agent to avoid getting 403 forbidden
     let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("user-agent", "kicad-wakatime/1.0".parse().unwrap());
+    headers.insert("user-agent", reqwest::header::HeaderValue::from_static("kicad-wakatime/1.0"));
     info!("Checking kicad-wakatime version");
     let res = client.get("https://api.github.com/repos/hackclub/kicad-wakatime/releases/latest")
       .headers(headers)
       .send()?;
       // .expect("Could not make request!");
-    let json = res.json::<serde_json::Value>().unwrap();
+    let json = res.json::<serde_json::Value>()?;
     // sanity check
     if let serde_json::Value::String(message) = &json["message"] {
       if message == &String::from("Not Found") {
@@ -278,7 +498,7 @@ impl Plugin {
     }
     let name = json["name"]
       .as_str()
-      .unwrap()
+      .context("Unexpected response from the GitHub API")?
       .to_string();
     if name != PLUGIN_VERSION {
       info!("kicad-wakatime update available!");
@@ -292,26 +512,28 @@ impl Plugin {
     let client = reqwest::blocking::Client::new();
     // need to insert some kind of user agent to avoid getting 403 forbidden
     let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("user-agent", "kicad-wakatime/1.0".parse().unwrap());
+    headers.insert("user-agent", reqwest::header::HeaderValue::from_static("kicad-wakatime/1.0"));
     // create .wakatime folder if it does not exist
-    // we will be downloading the .zip into there
-    if let Ok(false) = fs::exists(self.wakatime_folder_path()) {
-      fs::create_dir(self.wakatime_folder_path())?;
-    }
+    // we will be extracting the .zip into there
+    fs::create_dir_all(self.wakatime_folder_path())?;
+    fs::create_dir_all(paths::cache_dir())?;
     // get download URL
     info!("Getting latest version from GitHub API");
     let res = client.get("https://api.github.com/repos/wakatime/wakatime-cli/releases/latest")
       .headers(headers.clone())
       .send()?;
       // .expect("Could not make request!");
-    let json = res.json::<serde_json::Value>().unwrap();
+    let json = res.json::<serde_json::Value>()?;
     let asset = json["assets"]
       .as_array()
-      .unwrap()
+      .context("Unexpected response from the GitHub API")?
       .iter()
-      .find(|v| v["name"].as_str().unwrap().to_owned() == self.cli_zip_name(env_consts()))
-      .unwrap();
-    let download_url = asset["browser_download_url"].as_str().unwrap().to_owned();
+      .find(|v| v["name"].as_str() == Some(&self.cli_zip_name(env_consts())))
+      .with_context(|| format!("No WakaTime CLI release for {:?}", env_consts()))?;
+    let download_url = asset["browser_download_url"]
+      .as_str()
+      .context("Unexpected response from the GitHub API")?
+      .to_owned();
     // download .zip file
     info!("Downloading {download_url}...");
     let res = client.get(download_url)
@@ -319,9 +541,9 @@ impl Plugin {
       .send()?;
       // .expect("Could not make request!");
     let zip_bytes = res.bytes()?;
-    let mut zip_file = fs::File::create(self.cli_zip_path(env_consts())).unwrap();
+    let mut zip_file = fs::File::create(self.cli_zip_path(env_consts()))?;
     zip_file.write_all(&zip_bytes)?;
-    let zip_vec_u8: Vec<u8> = fs::read(self.cli_zip_path(env_consts())).unwrap();
+    let zip_vec_u8: Vec<u8> = fs::read(self.cli_zip_path(env_consts()))?;
     // extract .zip file
     info!("Extracting .zip...");
     zip_extract::extract(
@@ -336,124 +558,139 @@ impl Plugin {
     Ok(())
   }
   pub fn load_config(&mut self) -> Result<(), anyhow::Error> {
-    // wakatime config
-    let wakatime_cfg_path = self.wakatime_cfg_path();
-    if !fs::exists(&wakatime_cfg_path).unwrap() {
-      Ini::new().write_to_file(&wakatime_cfg_path)?;
-    }
-    self.wakatime_config = Ini::load_from_file(&wakatime_cfg_path).unwrap();
-    // kicad-wakatime config
-    let kicad_wakatime_cfg_path = self.kicad_wakatime_cfg_path();
-    if !fs::exists(&kicad_wakatime_cfg_path).unwrap() {
-      Ini::new().write_to_file(&kicad_wakatime_cfg_path)?;
-    }
-    self.kicad_wakatime_config = Ini::load_from_file(&kicad_wakatime_cfg_path).unwrap();
+    let (config, wakatime_config, kicad_wakatime_config) = Config::load(
+      &self.wakatime_cfg_path(),
+      &self.kicad_wakatime_cfg_path()
+    )?;
+    self.file_config = config.clone();
+    self.config = config;
+    self.overrides.apply(&mut self.config);
+    self.settings_changed();
+    for overridden in self.overrides.overridden() {
+      info!("Settings: {overridden} overridden from the command line or environment");
+    }
+    for problem in self.config.validate() {
+      warn!("Settings: {problem}");
+    }
+    self.wakatime_config = wakatime_config;
+    self.kicad_wakatime_config = kicad_wakatime_config;
     Ok(())
   }
-  pub fn store_config(&self) -> Result<(), anyhow::Error> {
-    Ini::write_to_file(&self.wakatime_config, self.wakatime_cfg_path())?;
-    Ini::write_to_file(&self.kicad_wakatime_config, self.kicad_wakatime_cfg_path())?;
+  /// Save the settings. The config files are read again first, and only the settings changed
+  /// in kicad-wakatime are written, so that changes made by other programs are not lost.
+  pub fn store_config(&mut self) -> Result<(), anyhow::Error> {
+    let mut config = self.config.clone();
+    self.overrides.unapply(&mut config, &self.file_config);
+    let (config, wakatime_config, kicad_wakatime_config) = Config::save(
+      &self.file_config,
+      &config,
+      &self.wakatime_cfg_path(),
+      &self.kicad_wakatime_cfg_path()
+    )?;
+    self.wakatime_config = wakatime_config;
+    self.kicad_wakatime_config = kicad_wakatime_config;
+    self.file_config = config.clone();
+    self.config = config;
+    self.overrides.apply(&mut self.config);
+    self.settings_changed();
     Ok(())
   }
-  pub fn set_api_key(&mut self, api_key: String) {
-    self.wakatime_config.with_section(Some("settings"))
-      .set("api_key", api_key);
-  }
-  pub fn get_api_key(&mut self) -> String {
-    match self.wakatime_config.with_section(Some("settings")).get("api_key") {
-      Some(api_key) => api_key.to_string(),
-      None => String::new(),
+  /// Keep the API keys out of the log, and make sure the WakaTime CLI gets the new settings.
+  fn settings_changed(&mut self) {
+    for api_key in [&self.config.api_key, &self.file_config.api_key].into_iter().flatten() {
+      scrub::add_secret(api_key);
     }
+    self.cli_config = None;
   }
-  pub fn set_api_url(&mut self, api_url: String) {
-    self.wakatime_config.with_section(Some("settings"))
-      .set("api_url", api_url);
-  }
-  pub fn get_api_url(&mut self) -> String {
-    match self.wakatime_config.with_section(Some("settings")).get("api_url") {
-      Some(api_url) => api_url.to_string(),
-      None => String::new(),
-    }
+  /// Return the sender that file watchers report changes to.
+  fn watcher_tx(&self) -> Result<Sender<notify::Result<notify::Event>>, anyhow::Error> {
+    self.tx.clone().context("The file watcher channel has not been set up")
   }
-  pub fn set_projects_file(&mut self, projects_file: String) {
-    self.kicad_wakatime_config.with_section(Some("settings"))
-      .set("projects_file", projects_file);
+  /// Start watching the config files, so that changes made by other programs
+  /// (e.g. an installer writing a new API key) are picked up without a restart.
+  pub fn watch_config(&mut self) -> Result<(), anyhow::Error> {
+    let watcher = watch_files(
+      self.watcher_tx()?,
+      vec![self.wakatime_cfg_path(), self.kicad_wakatime_cfg_path()],
+    )?;
+    self.config_watcher = Some(watcher);
+    Ok(())
   }
-  pub fn get_projects_file(&mut self) -> PathBuf {
-    match self.kicad_wakatime_config.with_section(Some("settings")).get("projects_file") {
-      Some(projects_file) => PathBuf::from(projects_file),
-      None => PathBuf::new(),
+  /// Load the config files again after they changed on disk.
+  pub fn reload_config(&mut self) -> Result<(), anyhow::Error> {
+    let (config, wakatime_config, kicad_wakatime_config) = Config::load(
+      &self.wakatime_cfg_path(),
+      &self.kicad_wakatime_cfg_path()
+    )?;
+    self.wakatime_config = wakatime_config;
+    self.kicad_wakatime_config = kicad_wakatime_config;
+    if config == self.file_config {
+      return Ok(())
     }
-  }
-  pub fn set_symbol_file(&mut self, projects_folder: String) {
-    self.kicad_wakatime_config.with_section(Some("settings"))
-      .set("symbol_file", projects_folder);
-  }
-  pub fn get_symbol_file(&mut self) -> PathBuf {
-    match self.kicad_wakatime_config.with_section(Some("settings")).get("symbol_file") {
-      Some(projects_folder) => PathBuf::from(projects_folder),
-      None => PathBuf::new(),
+    info!("Settings changed on disk, reloading");
+    let projects_changed = config.projects_files != self.file_config.projects_files ||
+      config.auto_track != self.file_config.auto_track;
+    self.file_config = config.clone();
+    self.config = config;
+    self.overrides.apply(&mut self.config);
+    self.settings_changed();
+    for problem in self.config.validate() {
+      warn!("Settings: {problem}");
     }
-  }
-  pub fn set_footprint_folder(&mut self, projects_folder: String) {
-    self.kicad_wakatime_config.with_section(Some("settings"))
-      .set("footprint_folder", projects_folder);
-  }
-  pub fn get_footprint_folder(&mut self) -> PathBuf {
-    match self.kicad_wakatime_config.with_section(Some("settings")).get("footprint_folder") {
-      Some(projects_folder) => PathBuf::from(projects_folder),
-      None => PathBuf::new(),
+    if projects_changed {
+      self.watch_projects()?;
     }
+    Ok(())
   }
-  pub fn language(&self) -> String {
-    if self.filename.ends_with(".kicad_sch") {
-      String::from("KiCAD Schematic")
-    } else if self.filename.ends_with(".kicad_pcb") {
-      String::from("KiCAD PCB")
-    } else if self.filename.ends_with(".kicad_sym") {
-      String::from("KiCAD Symbol")
-    } else if self.filename.ends_with(".kicad_mod") {
-      String::from("KiCAD Footprint")
-    } else {
-      error!("Unknown language for {}", self.filename);
-      unreachable!()
-    }
+  pub fn language(&self) -> Option<String> {
+    language_of(&self.filename)
   }
+  /// Return the full path of a file in any tracked project.
+  /// If several projects have a file with that name, the focused project wins.
   pub fn get_full_path(&self, filename: String) -> Option<&PathBuf> {
-    self.full_paths.get(&filename)
-  }
-  pub fn recursively_add_full_paths(&mut self, path: PathBuf) -> Result<(), anyhow::Error> {
-    for path in fs::read_dir(path)? {
-      let path = path.unwrap().path();
-      if path.is_dir() { self.recursively_add_full_paths(path.clone())?; };
-      if !path.is_file() { continue; };
-      let file_name = path.file_name().unwrap().to_str().unwrap();
-      // let file_stem = path.file_stem().unwrap().to_str().unwrap();
-      let Some(file_extension) = path.extension() else { continue; };
-      let file_extension = file_extension.to_str().unwrap();
-      if file_extension == "kicad_sch" || file_extension == "kicad_pcb" {
-        if self.full_paths.contains_key(file_name) {
-          error!("Found multiple files named {file_name} in the projects folder!");
-          error!("Please select a folder that only contains one file named {file_name}!");
-          self.full_paths = HashMap::new();
-          return Ok(())
-        }
-        self.full_paths.insert(
-          file_name.to_string(),
-          path
-        );
-      }
-    }
-    Ok(())
+    self.projects.iter()
+      .filter(|p| p.stem() == self.focused_project)
+      .chain(self.projects.iter())
+      .find_map(|p| p.get_full_path(&filename))
+  }
+  /// Return the symbol library being edited, from the user's settings and the tracked projects' libraries.
+  /// If there is more than one, the one whose name the window title starts with (`library:symbol`) wins.
+  pub fn find_symbol_file(&self, title: &str) -> Option<PathBuf> {
+    let symbol_files = self.config.symbol_file.iter()
+      .chain(self.projects.iter().flat_map(|p| p.config.symbol_files.iter()))
+      .collect::<Vec<_>>();
+    let library = title.split_once(':').map(|(library, _)| library);
+    symbol_files.iter()
+      .find(|f| library.is_some() && f.file_stem().map(|s| s.to_string_lossy()).as_deref() == library)
+      .or(symbol_files.first())
+      .map(|f| f.to_path_buf())
+  }
+  /// Return the footprint library containing the footprint being edited,
+  /// from the user's settings and the tracked projects' libraries.
+  pub fn find_footprint_folder(&self, footprint: &str) -> Option<PathBuf> {
+    let footprint_folders = self.config.footprint_folder.iter()
+      .chain(self.projects.iter().flat_map(|p| p.config.footprint_folders.iter()))
+      .collect::<Vec<_>>();
+    footprint_folders.iter()
+      .find(|f| f.join(format!("{footprint}.kicad_mod")).is_file())
+      .or(footprint_folders.first())
+      .map(|f| f.to_path_buf())
+  }
+  /// Return the tracked project a path belongs to.
+  pub fn project_of(&self, path: &std::path::Path) -> Option<&Project> {
+    self.projects.iter().find(|p| p.contains(path))
   }
   pub fn set_current_file(&mut self, filename: String) -> Result<(), anyhow::Error> {
-    if self.filename != filename {
+    if self.resumed_from_idle {
+      info!("Back from being idle, sending heartbeat");
+      self.maybe_send_heartbeat(filename.clone(), Trigger::Focus)?;
+    } else if self.filename != filename {
       info!("Focused file changed!");
       // since the focused file changed, it might be time to send a heartbeat.
       // self.filename and self.path are not actually updated here,
       // so self.maybe_send_heartbeat() can use the difference as a condition in its check
       info!("Filename: {}", filename.clone());
-      self.maybe_send_heartbeat(filename.clone(), false)?;
+      self.maybe_send_heartbeat(filename.clone(), Trigger::Focus)?;
       debug!("self.filename = {:?}", self.filename.clone());
       debug!("self.full_path = {:?}", self.full_path.clone());
     } else {
@@ -461,83 +698,206 @@ impl Plugin {
     }
     Ok(())
   }
-  pub fn look_at_backups_of_filename(
-    &mut self,
-    filename: String,
-    backups_folder: PathBuf
-  ) -> Result<(), anyhow::Error> {
-    // get all backups from the backups folder sorted by creation time
-    info!("Looking at backups of {filename}...");
-    std::thread::sleep(Duration::from_millis(500));
-    let mut backups = fs::read_dir(backups_folder)?
-      .flatten()
-      .map(|x| x.path())
-      .collect::<Vec<_>>();
-    backups.sort_by_key(|x| x.metadata().unwrap().created().unwrap());
-    let backups_count = backups.len();
-
-    if backups_count <= 1 {
-        // Skip a heartbeat, it will not matter in the long term tbh
-        return Ok(());
+  /// Send a heartbeat every so often while the same document stays focused and the user is active,
+  /// so that long sessions without saves are counted.
+  pub fn keep_alive(&mut self, filename: String) -> Result<(), anyhow::Error> {
+    let Some(interval) = self.config.keep_alive_interval() else { return Ok(()) };
+    if self.filename != filename || self.is_idle || self.is_paused() || !self.enough_time_passed() {
+      return Ok(())
     }
-
-    let mut v1: Vec<u8> = vec![];
-    let mut v2: Vec<u8> = vec![];
-    let p1 = &backups[backups_count - 1];
-    let p2 = &backups[backups_count - 2];
-    let f1 = File::open(p1)?;
-    let f2 = File::open(p2)?;
-    let mut newest_backup = ZipArchive::new(f1)?;
-    let mut second_newest_backup = ZipArchive::new(f2)?;
-
-    if let Ok(mut newest_backup_of_filename) = newest_backup.by_name(&filename) {
-        if let Ok(mut second_newest_backup_of_filename) = second_newest_backup.by_name(&filename) {
-            newest_backup_of_filename.read_to_end(&mut v1)?;
-            second_newest_backup_of_filename.read_to_end(&mut v2)?;
-            if v1.ne(&v2) {
-              info!("Change detected!");
-              self.maybe_send_heartbeat(filename, false)?;
-            } else {
-              info!("No change detected!");
-            }
-        } else {
-            error!("Error getting {} in backup!", filename);
-        }
-    } else {
-        error!("Error getting {} in backup!", filename);
+    // a heartbeat that is not sent (e.g. for an ignored file) is not tried again straight away
+    let since = self.last_sent_time.max(self.last_keep_alive_time);
+    if self.current_time() - since < interval {
+      return Ok(())
+    }
+    self.last_keep_alive_time = self.current_time();
+    info!("Still working on {filename}, sending keep-alive heartbeat");
+    self.maybe_send_heartbeat(filename, Trigger::KeepAlive)
+  }
+  /// Compare the two newest backups of a project and send heartbeats for every document that changed.
+  pub fn look_at_backups(&mut self, i: usize) -> Result<(), anyhow::Error> {
+    let project = &self.projects[i];
+    info!("Looking at backups in {:?}...", project.backups_folder);
+    let Some((second_newest_backup, newest_backup)) = project.backup_index.newest_two() else {
+      // Skip a heartbeat, it will not matter in the long term tbh
+      return Ok(());
+    };
+    let changes = backups::diff_backups(second_newest_backup, newest_backup)?;
+    if changes.is_empty() {
+      info!("No change detected!");
+      return Ok(())
+    }
+    for change in &changes {
+      info!("Change detected! ({:?} {})", change.change, change.name);
+    }
+    // the focused file goes through the usual checks...
+    let focused = PathBuf::from(&self.filename);
+    let focused = focused.file_name().unwrap_or_default().to_string_lossy();
+    if changes.iter().any(|c| c.file_name() == focused && c.change != Change::Removed) {
+      self.maybe_send_heartbeat(self.filename.clone(), Trigger::Backup)?;
+    }
+    // ...and every other sheet that changed gets a heartbeat of its own
+    for change in changes {
+      if change.file_name() == focused || change.change == Change::Removed {
+        continue
+      }
+      let Some(full_path) = self.projects[i].get_full_path(change.file_name()).cloned() else {
+        debug!("{} is not in the project folder, not sending heartbeat", change.name);
+        continue
+      };
+      // the same limit as for the focused file, but kept per file
+      if !self.enough_time_passed_for(&full_path) {
+        debug!("Not sending heartbeat for {} (one was sent recently)", change.name);
+        continue
+      }
+      self.send_heartbeat_for(full_path, Trigger::Backup)?;
     }
     Ok(())
   }
-  pub fn create_file_watcher(&mut self) -> Result<(), anyhow::Error> {
-    self.file_watcher = Some(notify::recommended_watcher(self.tx.clone().unwrap())?);
+  /// Delete backups of a project that are redundant or not needed by the retention policy,
+  /// depending on the prune mode.
+  pub fn prune_backups(&mut self, i: usize) -> Result<(), anyhow::Error> {
+    let prune_mode = self.config.prune_mode;
+    if prune_mode == PruneMode::Off {
+      return Ok(())
+    }
+    let project = &mut self.projects[i];
+    let plan = project.pruner.plan(&project.backup_index, SystemTime::now())?;
+    debug!("{} of {} backups to prune", plan.delete.len(), project.backup_index.len());
+    project.pruner.execute(&plan, &mut project.backup_index, prune_mode == PruneMode::DryRun)
+  }
+  /// Start tracking every project in the settings, replacing the projects tracked before.
+  pub fn watch_projects(&mut self) -> Result<(), anyhow::Error> {
+    let tx = self.watcher_tx()?;
+    let backup_path = self.kicad_common.as_ref().and_then(|k| k.backup_path());
+    self.projects = vec![];
+    let mut projects_files = self.config.projects_files.clone();
+    if self.config.auto_track {
+      projects_files.extend(self.suggested_projects.iter().cloned());
+    }
+    for projects_file in projects_files {
+      let mut project = Project::new(projects_file);
+      // one broken project should not stop the others from being tracked
+      match project.watch(tx.clone(), backup_path.clone()) {
+        Ok(()) => self.projects.push(project),
+        Err(e) => error!("Could not track {:?}: {:?}", project.file, e),
+      }
+    }
     Ok(())
   }
-  pub fn watch_files(&mut self, path: PathBuf) -> Result<(), anyhow::Error> {
-    if path == PathBuf::from("") {
+  /// Start watching KiCAD's kicad.json, which lists the projects KiCAD has open.
+  pub fn watch_kicad_projects(&mut self) -> Result<(), anyhow::Error> {
+    self.kicad_json_path = KicadProjectManager::find();
+    let Some(ref kicad_json_path) = self.kicad_json_path else {
+      warn!("Could not find kicad.json, unable to see which projects KiCAD has open");
       return Ok(())
+    };
+    let watcher = watch_files(self.watcher_tx()?, vec![kicad_json_path.clone()])?;
+    self.kicad_json_watcher = Some(watcher);
+    self.discover_projects()
+  }
+  /// Suggest tracking the projects KiCAD has open, and the recently opened project
+  /// that matches the focused window, if they are not in the settings yet.
+  /// With auto-tracking on, they are tracked straight away.
+  pub fn discover_projects(&mut self) -> Result<(), anyhow::Error> {
+    let Some(kicad_json_path) = self.kicad_json_path.clone() else { return Ok(()) };
+    // KiCAD may be halfway through writing it, keep the projects found so far and try again
+    // when it changes next
+    let project_manager = match KicadProjectManager::load(kicad_json_path.clone()) {
+      Ok(project_manager) => project_manager,
+      Err(e) => {
+        warn!("Could not read {:?}, keeping the projects found before: {e:#}", kicad_json_path);
+        return Ok(())
+      },
+    };
+    let mut suggested_projects = project_manager.open_projects();
+    let focused_project = project_manager.recent_projects()
+      .into_iter()
+      .find(|p| p.file_stem().is_some_and(|stem| stem.to_string_lossy() == self.focused_project));
+    if let Some(focused_project) = focused_project {
+      if !suggested_projects.contains(&focused_project) {
+        suggested_projects.push(focused_project);
+      }
+    }
+    suggested_projects.retain(|p| !self.config.projects_files.contains(p));
+    if suggested_projects == self.suggested_projects {
+      return Ok(())
+    }
+    for suggested_project in &suggested_projects {
+      info!("KiCAD has {:?} open", suggested_project);
+    }
+    self.suggested_projects = suggested_projects;
+    if self.config.auto_track {
+      self.watch_projects()?;
     }
-    info!("Watching {:?} for changes", path);
-    self.create_file_watcher()?;
-    self.file_watcher.as_mut().unwrap().watch(path.as_path(), RecursiveMode::Recursive)?;
-    self.full_paths = HashMap::new();
-    self.recursively_add_full_paths(path.clone())?;
-    debug!("full_paths = {:?}", self.full_paths);
     Ok(())
   }
+  /// Add a suggested project to the settings.
+  pub fn track_suggested_project(&mut self, projects_file: PathBuf) -> Result<(), anyhow::Error> {
+    self.suggested_projects.retain(|p| p != &projects_file);
+    self.config.projects_files.push(projects_file);
+    self.store_config()?;
+    self.watch_projects()
+  }
   pub fn try_recv(&mut self) -> Result<(), anyhow::Error> {
-    let Some(ref rx) = self.rx else { unreachable!(); };
-    let recv = rx.try_recv();
-    if recv.is_ok() {
-      if let Ok(Ok(notify::Event { kind, paths, attrs: _ })) = recv {
-        let path = paths[0].clone();
-        let is_backup = path.parent().unwrap().to_str().unwrap().ends_with("-backups");
-        if path == self.full_path {
-          info!("File saved!");
-          self.maybe_send_heartbeat(self.filename.clone(), true)?;
-        } else if is_backup && kind.is_create() {
-          info!("New backup created!");
-          self.look_at_backups_of_filename(self.filename.clone(), path.parent().unwrap().to_path_buf())?;
+    let rx = self.rx.as_ref().context("The file watcher channel has not been set up")?;
+    // handle everything that piled up since the last call, so that bursts of events don't lag behind
+    let events: Vec<notify::Event> = rx.try_iter().flatten().collect();
+    for event in events {
+      if let Err(e) = self.handle_event(event) {
+        error!("{:?}", e);
+      }
+    }
+    let now = self.current_time();
+    for i in 0..self.projects.len() {
+      let project = &mut self.projects[i];
+      if project.backup_created_time.is_some_and(|t| now - t >= Duration::from_millis(500)) {
+        project.backup_created_time = None;
+        // a failure in one project should not hold up the others
+        if let Err(e) = self.look_at_backups(i) {
+          error!("Could not look at the backups of {:?}: {:?}", self.projects[i].file, e);
         }
+        if let Err(e) = self.prune_backups(i) {
+          error!("Could not prune the backups of {:?}: {:?}", self.projects[i].file, e);
+        }
+      }
+    }
+    Ok(())
+  }
+  /// React to a file that changed on disk.
+  fn handle_event(&mut self, event: notify::Event) -> Result<(), anyhow::Error> {
+    let notify::Event { kind, paths, attrs: _ } = event;
+    let Some(path) = paths.into_iter().next() else { return Ok(()) };
+    let now = self.current_time();
+    if kind.is_create() {
+      // KiCAD creates a backups folder outside the project folder when making the first backup
+      for project in self.projects.iter_mut().filter(|p| p.backups_folder.starts_with(&path)) {
+        project.update_backups_watch()?;
+      }
+    }
+    let is_config = path == self.wakatime_cfg_path() || path == self.kicad_wakatime_cfg_path();
+    let backup_of = self.projects.iter_mut().find(|p| p.is_backup(&path));
+    if Some(&path) == self.kicad_json_path.as_ref() {
+      self.discover_projects()?;
+    } else if is_config {
+      self.reload_config()?;
+    } else if path.file_name().is_some_and(|n| n == PROJECT_CONFIG_NAME) {
+      if let Some(project) = self.projects.iter_mut().find(|p| path.parent() == Some(p.folder.as_path())) {
+        info!("Reloading {:?}", path);
+        project.load_config();
+      }
+    } else if path == self.full_path {
+      info!("File saved!");
+      self.activity();
+      self.maybe_send_heartbeat(self.filename.clone(), Trigger::Save)?;
+    } else if let Some(project) = backup_of {
+      if kind.is_create() {
+        info!("New backup of {} created!", project.name);
+        project.backup_index.insert(path);
+        // KiCAD may still be writing the backup, so look at it a bit later
+        project.backup_created_time = Some(now);
+      } else if kind.is_remove() {
+        project.backup_index.remove(&path);
       }
     }
     Ok(())
@@ -556,12 +916,17 @@ impl Plugin {
   pub fn enough_time_passed(&self) -> bool {
     self.time_passed() > Duration::from_secs(120)
   }
+  /// Returns `true` if more than 2 minutes have passed since the last heartbeat for a file.
+  pub fn enough_time_passed_for(&self, full_path: &Path) -> bool {
+    self.last_sent_times.get(full_path)
+      .is_none_or(|t| self.current_time() - *t > Duration::from_secs(120))
+  }
   /// Send a heartbeat if conditions are met.
   /// This is an analog of vscode-wakatime's `private onEvent(isWrite)`.
   pub fn maybe_send_heartbeat(
     &mut self,
     filename: String,
-    is_file_saved: bool
+    trigger: Trigger
   ) -> Result<(), anyhow::Error> {
     debug!("Determining whether to send heartbeat...");
     if self.last_sent_time == Duration::ZERO {
@@ -573,55 +938,115 @@ impl Plugin {
       debug!("Not sending heartbeat (too fast!)");
       return Ok(())
     }
-    if is_file_saved ||
+    if trigger == Trigger::Save ||
     self.enough_time_passed() ||
+    self.resumed_from_idle ||
     self.filename != filename {
-      self.filename = filename.clone();
-      self.full_path = if filename.ends_with(".kicad_sym") || filename.ends_with(".kicad_mod") {
-        PathBuf::from(filename)
+      self.resumed_from_idle = false;
+      let full_path = if filename.ends_with(".kicad_sym") || filename.ends_with(".kicad_mod") {
+        PathBuf::from(&filename)
       } else {
-        self.get_full_path(filename.clone()).unwrap().to_path_buf()
+        let Some(full_path) = self.get_full_path(filename.clone()) else {
+          warn!("Not sending heartbeat (can't find {filename} in any tracked project)");
+          return Ok(())
+        };
+        full_path.clone()
       };
+      self.filename = filename;
+      self.full_path = full_path;
 
-      self.send_heartbeat(is_file_saved)?;
+      self.send_heartbeat(trigger)?;
     } else {
       debug!("Not sending heartbeat (no conditions met)");
     }
     Ok(())
   }
-  pub fn send_heartbeat(&mut self, is_file_saved: bool) -> Result<(), anyhow::Error> {
+  /// Send a heartbeat for the focused file.
+  pub fn send_heartbeat(&mut self, trigger: Trigger) -> Result<(), anyhow::Error> {
+    if !self.send_heartbeat_for(self.full_path.clone(), trigger)? {
+      return Ok(())
+    }
+    self.last_sent_time = self.current_time();
+    self.last_sent_time_chrono = Some(Local::now());
+    self.last_sent_file = self.full_path.to_string_lossy().to_string();
+    debug!("last_sent_time = {:?}", self.last_sent_time);
+    debug!("last_sent_file = {:?}", self.last_sent_file);
+    Ok(())
+  }
+  /// Send a heartbeat for a file, which does not have to be the focused one.
+  /// The WakaTime CLI runs in the background, see [`Plugin::check_heartbeats`].
+  /// Returns whether a heartbeat was sent.
+  pub fn send_heartbeat_for(
+    &mut self,
+    full_path: PathBuf,
+    trigger: Trigger
+  ) -> Result<bool, anyhow::Error> {
     info!("Sending heartbeat...");
     if self.disable_heartbeats {
       warn!("Heartbeats are disabled (using --disable-heartbeats)");
       warn!("Updating last_sent_time anyway");
-      self.last_sent_time = self.current_time();
-      self.last_sent_time_chrono = Some(Local::now());
-      return Ok(())
+      self.last_sent_times.insert(full_path, self.current_time());
+      return Ok(true)
+    }
+    if self.pause.is_some() {
+      debug!("Not sending heartbeat (paused)");
+      return Ok(false)
+    }
+    if self.in_quiet_hours() {
+      debug!("Not sending heartbeat (quiet hours)");
+      return Ok(false)
     }
-    let full_path = self.full_path.clone();
-    let full_path_string = full_path.clone().into_os_string().into_string().unwrap();
+    if self.is_idle {
+      debug!("Not sending heartbeat (idle)");
+      return Ok(false)
+    }
+    let full_path_string = full_path.to_string_lossy().to_string();
     let quoted_full_path = format!("\"{full_path_string}\"");
     let plugin_version = self.version;
     // TODO: populate again
     let kicad_version = "unknown";
     let quoted_user_agent = format!("\"kicad/{kicad_version} kicad-wakatime/{plugin_version}\"");
-    let api_key = self.get_api_key();
-    let quoted_api_key = format!("\"{api_key}\"");
-    let api_url = self.get_api_url();
-    let quoted_api_url = format!("\"{api_url}\"");
-    let language = self.language();
+    let Some(language) = language_of(&full_path_string) else {
+      warn!("Not sending heartbeat (unknown file type {full_path_string})");
+      return Ok(false)
+    };
     let quoted_language = format!("\"{language}\"");
-    let file_stem = full_path.clone().file_stem().unwrap().to_str().unwrap().to_string();
+    // files outside of a tracked project (e.g. libraries) are their own project
+    let tracked_project = self.project_of(&full_path);
+    let relative_path = tracked_project.and_then(|p| p.relative_path(&full_path));
+    if let (Some(p), Some(relative_path)) = (tracked_project, &relative_path) {
+      if p.config.is_ignored(relative_path) {
+        debug!("Not sending heartbeat ({relative_path} is ignored by {})", p.name);
+        return Ok(false)
+      }
+    }
+    let category = match (tracked_project, &relative_path) {
+      (Some(p), Some(relative_path)) => p.config.category(relative_path).map(str::to_string),
+      _ => None,
+    };
+    let project = match tracked_project {
+      Some(project) => project.name.clone(),
+      None => full_path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+    };
     // create process
     let cli_path = self.cli_path(env_consts());
     let mut cli = std::process::Command::new(cli_path);
     cli.args(["--entity", &quoted_full_path]);
     cli.args(["--plugin", &quoted_user_agent]);
-    cli.args(["--key", &quoted_api_key]);
-    cli.args(["--api-url", &quoted_api_url]);
+    // the API key is never passed as an argument, since other users can see those.
+    // the CLI reads it from .wakatime.cfg, unless it was set from somewhere else
+    if self.config.api_key != self.file_config.api_key || self.config.api_url != self.file_config.api_url {
+      if !self.cli_config.as_ref().is_some_and(|c| c.is_up_to_date(&self.config)) {
+        self.cli_config = Some(CliConfig::write(&self.wakatime_config, &self.config)?);
+      }
+      cli.arg("--config").arg(&self.cli_config.as_ref().unwrap().path);
+    }
     cli.args(["--language", &quoted_language]);
-    cli.args(["--project", &file_stem]);
-    if is_file_saved {
+    cli.args(["--project", &project]);
+    if let Some(ref category) = category {
+      cli.args(["--category", category]);
+    }
+    if trigger == Trigger::Save {
       cli.arg("--write");
     }
     #[cfg(windows)]
@@ -629,43 +1054,75 @@ impl Plugin {
       cli.creation_flags(0x08000000); // CREATE_NO_WINDOW
     }
     info!("Executing WakaTime CLI...");
-    let cli_output = cli.output()
-      .expect("Could not execute WakaTime CLI!");
-    let cli_status = cli_output.status;
-    let cli_stdout = cli_output.stdout;
-    let cli_stderr = cli_output.stderr;
-    // TODO: handle failing statuses (102/112, 103, 104)
-    debug!("cli_status = {cli_status}");
-    debug!("cli_stdout = {:?}", str::from_utf8(&cli_stdout).unwrap());
-    debug!("cli_stderr = {:?}", str::from_utf8(&cli_stderr).unwrap());
-    // heartbeat should have been sent at this point
-    info!("Finished!");
-    self.last_sent_time = self.current_time();
-    self.last_sent_time_chrono = Some(Local::now());
-    self.last_sent_file = full_path_string;
-    debug!("last_sent_time = {:?}", self.last_sent_time);
-    debug!("last_sent_file = {:?}", self.last_sent_file);
-    Ok(())
+    let entry = HistoryEntry {
+      time: Local::now(),
+      entity: full_path.clone(),
+      project,
+      editor: editor_of(&full_path_string).map(str::to_string),
+      category,
+      trigger,
+      result: Outcome::Failed,
+    };
+    // the CLI can take a while when the server is slow, so don't wait for it
+    let tx = self.heartbeats_tx.clone();
+    thread::spawn(move || {
+      let _ = tx.send((entry, cli.output()));
+    });
+    self.last_sent_times.insert(full_path, self.current_time());
+    Ok(true)
+  }
+  /// Record the heartbeats the WakaTime CLI finished sending.
+  pub fn check_heartbeats(&mut self) {
+    while let Ok((mut entry, cli_output)) = self.heartbeats_rx.try_recv() {
+      let cli_output = match cli_output {
+        Ok(cli_output) => cli_output,
+        Err(e) => {
+          self.record_heartbeat(&entry);
+          error!("Could not execute WakaTime CLI! {e}");
+          continue
+        },
+      };
+      let cli_status = cli_output.status;
+      // TODO: handle failing statuses (103, 104)
+      entry.result = Outcome::from_exit_code(cli_status.code());
+      self.record_heartbeat(&entry);
+      match entry.result {
+        Outcome::Sent => {},
+        Outcome::Queued => warn!("The heartbeat could not be sent yet, the WakaTime CLI will try again later"),
+        Outcome::Failed => warn!("The WakaTime CLI failed to send the heartbeat ({cli_status})"),
+      }
+      debug!("cli_status = {cli_status}");
+      debug!("cli_stdout = {:?}", String::from_utf8_lossy(&cli_output.stdout));
+      debug!("cli_stderr = {:?}", String::from_utf8_lossy(&cli_output.stderr));
+      info!("Finished sending heartbeat for {:?}", entry.entity);
+    }
+  }
+  /// Add a heartbeat to the history. Tracking goes on if that fails, so errors are only logged.
+  pub fn record_heartbeat(&self, entry: &HistoryEntry) {
+    if let Err(e) = self.history.append(entry) {
+      error!("Could not add the heartbeat to {:?}: {e}", self.history.path);
+    }
+  }
+  /// Remove old heartbeats from the history.
+  pub fn compact_history(&self) {
+    let keep_since = Local::now() - chrono::Duration::days(HISTORY_KEEP_DAYS);
+    match self.history.compact(keep_since) {
+      Ok(0) => {},
+      Ok(removed) => info!("Removed {removed} old heartbeat(s) from the history"),
+      Err(e) => error!("Could not compact {:?}: {e:#}", self.history.path),
+    }
   }
   /// Return the path to the .wakatime.cfg file.
   pub fn wakatime_cfg_path(&self) -> PathBuf {
-    let home_dir = home::home_dir().expect("Unable to get your home directory!");
-    home_dir.join(".wakatime.cfg")
+    paths::wakatime_cfg_path()
   }
-  /// Return the path to the .kicad-wakatime.cfg file.
+  /// Return the path to the kicad-wakatime.cfg file.
   pub fn kicad_wakatime_cfg_path(&self) -> PathBuf {
-    let home_dir = home::home_dir().expect("Unable to get your home directory!");
-    home_dir.join(".kicad-wakatime.cfg")
-  }
-  // /// Return the path to the .kicad-wakatime.log file.
-  // pub fn kicad_wakatime_log_path(&self) -> PathBuf {
-  //   let home_dir = home::home_dir().expect("Unable to get your home directory!");
-  //   home_dir.join(".kicad-wakatime.log")
-  // }
+    paths::kicad_wakatime_cfg_path()
+  }
   /// Return the path to the .wakatime folder.
   pub fn wakatime_folder_path(&self) -> PathBuf {
-    let home_dir = home::home_dir().expect("Unable to get your home directory!");
-    home_dir.join(".wakatime")
+    paths::wakatime_folder_path()
   }
   /// Return the file stem of the WakaTime CLI executable for the current OS and architecture.
   pub fn cli_name(&self, consts: (&'static str, &'static str)) -> String {
@@ -694,10 +1151,72 @@ impl Plugin {
     let cli_exe_name = self.cli_exe_name(consts);
     wakatime_folder_path.join(cli_exe_name)
   }
+  /// Return the number of heartbeats the WakaTime CLI has saved to send later,
+  /// e.g. because the computer was offline.
+  pub fn offline_count(&self) -> Result<u64, anyhow::Error> {
+    let mut cli = Command::new(self.cli_path(env_consts()));
+    cli.arg("--offline-count");
+    #[cfg(windows)]
+    {
+      cli.creation_flags(0x08000000); // CREATE_NO_WINDOW
+    }
+    let output = cli.output()?;
+    Ok(str::from_utf8(&output.stdout)?.trim().parse()?)
+  }
   /// Return the path to the downloaded WakaTime CLI .zip file for the current OS and architecture.
-  /// The file is downloaded into the .wakatime folder.
+  /// The file is downloaded into the cache folder.
   pub fn cli_zip_path(&self, consts: (&'static str, &'static str)) -> PathBuf {
-    self.wakatime_folder_path().join(self.cli_zip_name(consts))
+    paths::cache_dir().join(self.cli_zip_name(consts))
+  }
+}
+
+/// Watch files that programs replace rather than write to, by watching the folders they are in.
+/// The folders can be busy (e.g. the home folder), so only events for the files are passed on.
+fn watch_files(
+  tx: Sender<notify::Result<notify::Event>>,
+  files: Vec<PathBuf>,
+) -> Result<RecommendedWatcher, anyhow::Error> {
+  let folders: Vec<PathBuf> = files.iter().filter_map(|f| f.parent()).map(PathBuf::from).collect();
+  let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+    // reading the files is reported too, which would make them be read again and again
+    if event.as_ref().is_ok_and(|e| !e.kind.is_access() && e.paths.iter().any(|p| files.contains(p))) {
+      let _ = tx.send(event);
+    }
+  })?;
+  for folder in folders {
+    watcher.watch(&folder, RecursiveMode::NonRecursive)?;
+  }
+  Ok(watcher)
+}
+
+/// Return the WakaTime language of a KiCAD file.
+pub fn language_of(filename: &str) -> Option<String> {
+  if filename.ends_with(".kicad_sch") {
+    Some(String::from("KiCAD Schematic"))
+  } else if filename.ends_with(".kicad_pcb") {
+    Some(String::from("KiCAD PCB"))
+  } else if filename.ends_with(".kicad_sym") {
+    Some(String::from("KiCAD Symbol"))
+  } else if filename.ends_with(".kicad_mod") {
+    Some(String::from("KiCAD Footprint"))
+  } else {
+    error!("Unknown language for {}", filename);
+    None
+  }
+}
+
+/// Return the KiCAD editor a file is edited in.
+pub fn editor_of(filename: &str) -> Option<&'static str> {
+  if filename.ends_with(".kicad_sch") {
+    Some("Schematic Editor")
+  } else if filename.ends_with(".kicad_pcb") {
+    Some("PCB Editor")
+  } else if filename.ends_with(".kicad_sym") {
+    Some("Symbol Editor")
+  } else if filename.ends_with(".kicad_mod") {
+    Some("Footprint Editor")
+  } else {
+    None
   }
 }
 
@@ -0,0 +1,80 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Start a new log file when the current one gets bigger than this.
+pub const MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Start a new log file when the current one gets older than this.
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// Number of previous log files kept, as `<log>.1` (newest) to `<log>.<KEEP>`.
+pub const KEEP: u32 = 5;
+
+/// A log file that is rotated when it gets too big or too old, keeping the previous ones.
+/// Every start begins a new file, so the log of a session that crashed is kept as `<log>.1`.
+pub struct RotatingLog {
+  path: PathBuf,
+  file: File,
+  size: u64,
+  created: SystemTime,
+}
+
+impl RotatingLog {
+  pub fn open(path: &Path) -> Result<Self, io::Error> {
+    if fs::metadata(path).is_ok_and(|m| m.len() > 0) {
+      rotate_files(path)?;
+    }
+    Ok(RotatingLog {
+      path: path.to_path_buf(),
+      file: File::create(path)?,
+      size: 0,
+      created: SystemTime::now(),
+    })
+  }
+  fn rotate(&mut self) -> Result<(), io::Error> {
+    self.file.flush()?;
+    rotate_files(&self.path)?;
+    self.file = File::create(&self.path)?;
+    self.size = 0;
+    self.created = SystemTime::now();
+    Ok(())
+  }
+}
+
+impl Write for RotatingLog {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let too_old = self.created.elapsed().is_ok_and(|age| age > MAX_AGE);
+    if self.size > 0 && (self.size + buf.len() as u64 > MAX_SIZE || too_old) {
+      self.rotate()?;
+    }
+    let written = self.file.write(buf)?;
+    self.size += written as u64;
+    Ok(written)
+  }
+  fn flush(&mut self) -> io::Result<()> {
+    self.file.flush()
+  }
+}
+
+/// Return the path of a previous log file, where 1 is the newest.
+pub fn previous_log_path(path: &Path, i: u32) -> PathBuf {
+  let mut name = OsString::from(path.as_os_str());
+  name.push(format!(".{i}"));
+  PathBuf::from(name)
+}
+
+/// Move `<log>` to `<log>.1`, `<log>.1` to `<log>.2` and so on, deleting the oldest.
+fn rotate_files(path: &Path) -> Result<(), io::Error> {
+  let oldest = previous_log_path(path, KEEP);
+  if oldest.exists() {
+    fs::remove_file(oldest)?;
+  }
+  for i in (1..KEEP).rev() {
+    let from = previous_log_path(path, i);
+    if from.exists() {
+      fs::rename(from, previous_log_path(path, i + 1))?;
+    }
+  }
+  fs::rename(path, previous_log_path(path, 1))
+}
@@ -1,46 +1,209 @@
 #![windows_subsystem = "windows"]
 
-use std::{env, fs::File};
+use std::env;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::io::Write;
 use chrono::Local;
 use eframe::egui::{self};
 // use cocoa::appkit::NSApp;
 // use cocoa::appkit::NSApplication;
 // use cocoa::appkit::NSApplicationActivationPolicy::NSApplicationActivationPolicyRegular;
-use kicad_wakatime::{ui::Ui, Plugin};
-use clap::Parser;
+use kicad_wakatime::{cli_config, config::{Config, ConfigOverrides}, log_file::RotatingLog, control, crash, diagnostics, doctor, headless, instance::{self, InstanceLock}, paths, pause::{QuietHours, MAX_PAUSE_MINUTES}, prune::PruneMode, scrub::{scrub, Scrubber}, ui::Ui, Plugin};
+use clap::{Parser, Subcommand};
 use log::debug;
 use log::error;
 use log::info;
-// use log::warn;
+use log::warn;
 use multi_log::MultiLogger;
-use simplelog;
 
 /// WakaTime plugin for KiCAD
+///
+/// Settings given as flags or environment variables override the config files,
+/// but are not saved to them. Flags take precedence over environment variables.
 #[derive(Parser)]
 pub struct Args {
+  #[clap(subcommand)]
+  command: Option<Command>,
   #[clap(long)]
   disable_heartbeats: bool,
+  /// Track in the background without opening a window, using the saved settings.
+  /// Logs go to stderr as well as the log file. Stop with SIGTERM or Ctrl+C
+  #[clap(long)]
+  headless: bool,
   #[clap(long)]
   /// Redownload WakaTime CLI
   redownload: bool,
+  /// KiCAD project (.kicad_pro file) to track, can be used more than once
+  ///
+  /// [env: KICAD_WAKATIME_PROJECT, separated like PATH]
+  #[clap(long = "project", value_name = "KICAD_PRO", global = true)]
+  projects: Vec<PathBuf>,
+  /// WakaTime API key
+  #[clap(long, env = "WAKATIME_API_KEY", hide_env_values = true, global = true)]
+  api_key: Option<String>,
+  /// WakaTime API URL
+  #[clap(long, env = "WAKATIME_API_URL", global = true)]
+  api_url: Option<String>,
+  /// Symbol library (.kicad_sym file)
+  #[clap(long, env = "KICAD_WAKATIME_SYMBOL_LIB", value_name = "KICAD_SYM", global = true)]
+  symbol_lib: Option<PathBuf>,
+  /// Folder containing .kicad_mod files
+  #[clap(long, env = "KICAD_WAKATIME_FOOTPRINT_LIB", value_name = "FOLDER", global = true)]
+  footprint_lib: Option<PathBuf>,
+  /// Prune redundant backups (off, dry-run or on)
+  #[clap(long, env = "KICAD_WAKATIME_PRUNE_BACKUPS", value_parser = parse_prune_mode, global = true)]
+  prune_backups: Option<PruneMode>,
+  /// Automatically track the projects open in KiCAD
+  #[clap(long, env = "KICAD_WAKATIME_AUTO_TRACK", value_name = "BOOL", global = true)]
+  auto_track: Option<bool>,
+  /// Level of the log file (off, error, warn, info, debug or trace) [default: debug]
+  #[clap(long, env = "KICAD_WAKATIME_LOG_LEVEL", value_name = "LEVEL", global = true)]
+  log_level: Option<log::LevelFilter>,
+  /// Level of the log printed to stderr [default: warn, or info with --headless]
+  #[clap(long, env = "KICAD_WAKATIME_STDERR_LOG_LEVEL", value_name = "LEVEL", global = true)]
+  stderr_log_level: Option<log::LevelFilter>,
+  /// Stop sending heartbeats after this many seconds without activity, 0 to never stop [default: 300]
+  #[clap(long, env = "KICAD_WAKATIME_IDLE_TIMEOUT", value_name = "SECONDS", global = true)]
+  idle_timeout: Option<u64>,
+  /// Send a heartbeat this often while a document stays focused, 0 to turn off [default: 120]
+  #[clap(long, env = "KICAD_WAKATIME_KEEP_ALIVE_INTERVAL", value_name = "SECONDS", global = true)]
+  keep_alive_interval: Option<u64>,
+  /// Weekly time when no heartbeats are sent, e.g. "mon-fri 18:00-09:00" or "sat,sun",
+  /// can be used more than once
+  #[clap(long, env = "KICAD_WAKATIME_QUIET_HOURS", value_name = "DAYS TIMES", value_delimiter = ';', global = true)]
+  quiet_hours: Vec<QuietHours>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Control the running kicad-wakatime
+  Ctl {
+    #[clap(subcommand)]
+    command: CtlCommand,
+  },
+  /// Check everything kicad-wakatime needs and print a report
+  Doctor,
+  /// Save logs, redacted settings and what is being tracked to a .zip file for a bug report
+  Diagnostics {
+    /// Where to save the .zip file
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+  },
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+  /// Print the status, current document, last heartbeat and number of queued heartbeats as JSON
+  Status,
+  /// Stop sending heartbeats
+  Pause {
+    /// Start sending heartbeats again after this many minutes
+    #[clap(long = "for", value_name = "MINUTES", value_parser = clap::value_parser!(u64).range(1..=MAX_PAUSE_MINUTES))]
+    minutes: Option<u64>,
+  },
+  /// Start sending heartbeats again
+  Resume,
+  /// Look for files in the tracked projects again
+  Rescan,
+}
+
+impl Args {
+  fn config_overrides(&self) -> ConfigOverrides {
+    let projects_files: Vec<PathBuf> = if self.projects.is_empty() {
+      env::var_os("KICAD_WAKATIME_PROJECT")
+        .map(|projects| env::split_paths(&projects).filter(|p| !p.as_os_str().is_empty()).collect())
+        .unwrap_or_default()
+    } else {
+      self.projects.clone()
+    };
+    // relative paths are relative to where kicad-wakatime was started, not where it runs from later
+    let absolute = |path: &PathBuf| std::path::absolute(path).unwrap_or(path.clone());
+    ConfigOverrides {
+      api_key: self.api_key.clone(),
+      api_url: self.api_url.clone(),
+      projects_files: projects_files.iter().map(absolute).collect(),
+      symbol_file: self.symbol_lib.as_ref().map(absolute),
+      footprint_folder: self.footprint_lib.as_ref().map(absolute),
+      prune_mode: self.prune_backups,
+      auto_track: self.auto_track,
+      log_level: self.log_level,
+      stderr_log_level: self.stderr_log_level,
+      idle_timeout: self.idle_timeout,
+      keep_alive_interval: self.keep_alive_interval,
+      quiet_hours: self.quiet_hours.clone(),
+    }
+  }
+}
+
+/// Print to the console kicad-wakatime was started from, if there is one. On Windows it is
+/// built as a GUI program, which has no console of its own.
+fn attach_console() {
+  #[cfg(windows)]
+  {
+    #[link(name = "kernel32")]
+    extern "system" {
+      fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // fails when started from Explorer, and leaves output that is redirected to a file alone
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+  }
+}
+
+fn parse_prune_mode(s: &str) -> Result<PruneMode, String> {
+  s.parse().map_err(|e: anyhow::Error| e.to_string())
 }
 
 fn main() -> Result<(), anyhow::Error> {
   // pre-initialization
   env::set_var("RUST_BACKTRACE", "1");
-  let args = Args::parse();
-  // egui_logger
-  let egui_logger = Box::new(egui_logger::builder().build());
-  // need to find path like this because Plugin will not have been made yet
-  let home_dir = home::home_dir().expect("Unable to get your home directory!");
-  let kicad_wakatime_log_path = home_dir.join(".kicad-wakatime.log");
-  let target = Box::new(File::create(kicad_wakatime_log_path)?);
+  let args = Args::try_parse().unwrap_or_else(|e| {
+    // so that --help and mistakes in the arguments can be seen
+    attach_console();
+    e.exit()
+  });
+  if args.command.is_some() || args.headless {
+    attach_console();
+  }
+  match args.command {
+    Some(Command::Ctl { command }) => return ctl(command),
+    Some(Command::Doctor) => {
+      migrate_dotfiles();
+      let mut plugin = Plugin::new(false, false);
+      plugin.overrides = args.config_overrides();
+      if !doctor::run(&mut plugin) {
+        std::process::exit(1);
+      }
+      return Ok(())
+    },
+    Some(Command::Diagnostics { ref output }) => return diagnostics(&args, output.clone()),
+    None => {},
+  }
+  // only one kicad-wakatime should track at a time, and a second one must not touch the log
+  let Some(_instance_lock) = InstanceLock::acquire()? else {
+    attach_console();
+    return hand_off(&args);
+  };
+  // this has to happen before the log file is created
+  let migrated = paths::migrate_dotfiles()?;
+  // the log levels are needed before the settings are loaded properly
+  let mut config = Config::load(&paths::wakatime_cfg_path(), &paths::kicad_wakatime_cfg_path())
+    .map(|(config, _, _)| config)
+    .unwrap_or_default();
+  args.config_overrides().apply(&mut config);
+  let log_level = config.log_level.unwrap_or(log::LevelFilter::Debug);
+  let stderr_log_level = config.stderr_log_level.unwrap_or(match args.headless {
+    true => log::LevelFilter::Info,
+    false => log::LevelFilter::Warn,
+  });
+  let target = Box::new(RotatingLog::open(&paths::log_path())?);
   // env_logger
   let env_logger = Box::new(
     env_logger::Builder::new()
       .target(env_logger::Target::Pipe(target))
-      .filter(None, log::LevelFilter::Debug)
+      .filter(None, log_level)
       .format(|buf, record| {
         writeln!(
           buf,
@@ -54,12 +217,39 @@ fn main() -> Result<(), anyhow::Error> {
       })
       .build()
   );
-  let std_logger = simplelog::SimpleLogger::new(log::LevelFilter::Warn, simplelog::Config::default());
-  MultiLogger::init(vec![egui_logger, env_logger, std_logger], log::Level::Debug)
+  let loggers: Vec<Box<dyn log::Log>> = if args.headless {
+    let std_logger = simplelog::TermLogger::new(
+      stderr_log_level,
+      simplelog::Config::default(),
+      simplelog::TerminalMode::Stderr,
+      simplelog::ColorChoice::Auto
+    );
+    vec![env_logger, std_logger]
+  } else {
+    let egui_logger = Box::new(egui_logger::builder().build());
+    let std_logger = simplelog::SimpleLogger::new(stderr_log_level, simplelog::Config::default());
+    vec![egui_logger, env_logger, std_logger]
+  };
+  let multi_logger = MultiLogger::new(loggers);
+  // the window shows debug messages
+  let window_log_level = match args.headless {
+    true => log::LevelFilter::Off,
+    false => log::LevelFilter::Debug,
+  };
+  log::set_max_level(log_level.max(stderr_log_level).max(window_log_level));
+  log::set_boxed_logger(Box::new(Scrubber::new(Box::new(multi_logger))))
     .expect("Could not initialize multi logger!");
   log_panics::init();
+  crash::install_hook();
 
+  for migrated in migrated {
+    info!("{migrated}");
+  }
   debug!("(os, arch) = {:?}", kicad_wakatime::env_consts());
+  debug!("log file: {:?}", paths::log_path());
+  for stale in cli_config::remove_stale() {
+    debug!("Removed {:?}, left behind by an earlier kicad-wakatime", stale);
+  }
 
   let (tx, rx) = std::sync::mpsc::channel::<Result<notify::Event, notify::Error>>();
 
@@ -76,6 +266,11 @@ fn main() -> Result<(), anyhow::Error> {
   info!("Initializing kicad-wakatime...");
   plugin.tx = Some(tx);
   plugin.rx = Some(rx);
+  plugin.overrides = args.config_overrides();
+  plugin.last_crash = crash::last_crash();
+  if plugin.last_crash.is_some() {
+    warn!("kicad-wakatime crashed last time, the details are in {:?}", paths::crash_path());
+  }
 
   #[cfg(target_os = "macos")]
   {
@@ -87,17 +282,15 @@ fn main() -> Result<(), anyhow::Error> {
   }
 
   // settings population
-  plugin.load_config()?;
-  plugin.projects_file = plugin.get_projects_file().to_str().unwrap().to_string();
-  plugin.projects_folder = if plugin.projects_file == "" {
-      "".to_string()
-  } else { 
-      plugin.get_projects_file().parent().expect("Uh os problem").to_str().unwrap().to_string()
-  };
-  plugin.symbol = plugin.get_symbol_file().to_str().unwrap().to_string();
-  plugin.footprint = plugin.get_footprint_folder().to_str().unwrap().to_string();
-  plugin.api_key = plugin.get_api_key();
-  plugin.api_url = plugin.get_api_url();
+  if let Err(e) = plugin.load_config() {
+    error!("{:?}", e);
+    error!("Using default settings");
+  }
+
+  if args.headless {
+    headless::run(&mut plugin);
+    return Ok(())
+  }
 
   let _ = eframe::run_simple_native(
     "kicad-wakatime ^_^",
@@ -111,22 +304,133 @@ fn main() -> Result<(), anyhow::Error> {
           plugin.first_iteration_finished = true;
         }
       };
-      match plugin.main_loop() {
-        Ok(_) => {},
-        Err(e) => {
-          error!("{:?}", e);
-          plugin.first_iteration_finished = true;
-        }
-      };
-      match plugin.try_recv() {
-        Ok(_) => {},
-        Err(e) => {
-          error!("{:?}", e);
-        }
-      };
+      plugin.update();
       ctx.request_repaint();
     }
   );
 
   Ok(())
 }
+
+/// Send a command to the running kicad-wakatime, printing the response.
+fn ctl(command: CtlCommand) -> Result<(), anyhow::Error> {
+  let request = match command {
+    CtlCommand::Status => serde_json::json!({ "command": "status" }),
+    CtlCommand::Pause { minutes } => serde_json::json!({ "command": "pause", "minutes": minutes }),
+    CtlCommand::Resume => serde_json::json!({ "command": "resume" }),
+    CtlCommand::Rescan => serde_json::json!({ "command": "rescan" }),
+  };
+  let command = request["command"].clone();
+  let response = control::request(&request)?;
+  if response["ok"] != true {
+    anyhow::bail!("{}", response["error"].as_str().unwrap_or("unknown error"));
+  }
+  if command == "status" {
+    println!("{}", serde_json::to_string_pretty(&response)?);
+  }
+  Ok(())
+}
+
+/// Move the files older versions kept in the home folder, so that subcommands read the same
+/// settings as the tracker. A running kicad-wakatime has done this already, and owns the log.
+fn migrate_dotfiles() {
+  let migrated = match InstanceLock::acquire() {
+    Ok(Some(_instance_lock)) => paths::migrate_dotfiles(),
+    Ok(None) => return,
+    Err(e) => Err(e),
+  };
+  match migrated {
+    Ok(migrated) => migrated.iter().for_each(|m| eprintln!("{m}")),
+    Err(e) => eprintln!("Could not move the files of an older version of kicad-wakatime: {e}"),
+  }
+}
+
+/// Save a diagnostics bundle. What is being tracked comes from the running kicad-wakatime
+/// if there is one, otherwise the projects in the settings are looked through.
+fn diagnostics(args: &Args, output: Option<PathBuf>) -> Result<(), anyhow::Error> {
+  let output = output.unwrap_or_else(|| PathBuf::from(diagnostics::default_file_name()));
+  migrate_dotfiles();
+  let info = match control::send("diagnostics") {
+    Ok(response) if response["ok"] == true => response["diagnostics"].clone(),
+    _ => {
+      let mut plugin = Plugin::new(false, false);
+      plugin.overrides = args.config_overrides();
+      let (tx, rx) = std::sync::mpsc::channel();
+      plugin.tx = Some(tx);
+      plugin.rx = Some(rx);
+      // a broken setup is what diagnostics are for, so errors go in the report instead of stopping it
+      let mut errors = vec![];
+      if let Err(e) = plugin.load_config() {
+        errors.push(format!("Could not load the settings: {e:#}"));
+        plugin.overrides.apply(&mut plugin.config);
+      }
+      if let Err(e) = plugin.check_kicad_settings() {
+        errors.push(format!("Could not check KiCAD's settings: {e:#}"));
+      }
+      if let Err(e) = plugin.watch_projects() {
+        errors.push(format!("Could not look through the projects: {e:#}"));
+      }
+      for e in &errors {
+        eprintln!("{e}");
+      }
+      let mut info = plugin.diagnostics_info();
+      info["errors"] = errors.iter().map(|e| scrub(e)).collect();
+      info
+    },
+  };
+  diagnostics::export(&output, &info)?;
+  println!("Saved diagnostics to {:?}", output);
+  Ok(())
+}
+
+/// How long to keep trying to hand over to a kicad-wakatime that is still starting.
+const HAND_OFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Hand the project arguments over to the kicad-wakatime that is already running,
+/// and bring its window to the front.
+fn hand_off(args: &Args) -> Result<(), anyhow::Error> {
+  let pid = instance::running_pid().map(|pid| pid.to_string()).unwrap_or(String::from("unknown"));
+  allow_set_foreground_window();
+  let request = serde_json::json!({ "command": "activate", "projects": args.config_overrides().projects_files });
+  // the other kicad-wakatime may have only just started, and not be listening yet
+  let started = Instant::now();
+  let mut result = control::request(&request);
+  while result.is_err() && started.elapsed() < HAND_OFF_TIMEOUT {
+    thread::sleep(Duration::from_millis(250));
+    result = control::request(&request);
+  }
+  let result = match result {
+    Ok(response) if response["ok"] == true => {
+      println!("kicad-wakatime is already running (process {pid}), handed over to it");
+      Ok(())
+    },
+    Ok(response) => anyhow::bail!(
+      "kicad-wakatime is already running (process {pid}): {}",
+      response["error"].as_str().unwrap_or("unknown error")
+    ),
+    Err(e) => Err(anyhow::anyhow!("kicad-wakatime is already running (process {pid}), and could not hand over to it: {e:#}")),
+  };
+  // when started from Explorer there is no console to print the error to
+  if let (true, Err(e)) = (cfg!(windows), &result) {
+    rfd::MessageDialog::new()
+      .set_title("kicad-wakatime")
+      .set_description(format!("{e:#}"))
+      .set_level(rfd::MessageLevel::Warning)
+      .show();
+  }
+  result
+}
+
+/// Let the kicad-wakatime that is already running bring its window to the front. Windows only
+/// lets the program the user just started do that, unless it passes the permission on.
+fn allow_set_foreground_window() {
+  #[cfg(windows)]
+  {
+    #[link(name = "user32")]
+    extern "system" {
+      fn AllowSetForegroundWindow(process_id: u32) -> i32;
+    }
+    const ASFW_ANY: u32 = u32::MAX;
+    unsafe { AllowSetForegroundWindow(ASFW_ANY) };
+  }
+}
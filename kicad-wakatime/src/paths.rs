@@ -0,0 +1,147 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::log_file;
+
+const APP_NAME: &str = "kicad-wakatime";
+
+fn home_dir() -> PathBuf {
+  home::home_dir().expect("Unable to get your home directory!")
+}
+
+/// Return the value of an environment variable holding an absolute path, if it is set.
+fn env_path(key: &str) -> Option<PathBuf> {
+  env::var_os(key)
+    .map(PathBuf::from)
+    .filter(|path| path.is_absolute())
+}
+
+/// Return the folder the WakaTime CLI keeps .wakatime.cfg and the .wakatime folder in.
+/// Like the other WakaTime plugins, this is `WAKATIME_HOME` if it is set.
+pub fn wakatime_home() -> PathBuf {
+  env_path("WAKATIME_HOME").unwrap_or_else(home_dir)
+}
+
+/// Return the folder kicad-wakatime keeps its settings in.
+/// This is `$XDG_CONFIG_HOME/kicad-wakatime`, or the usual per-OS location.
+pub fn config_dir() -> PathBuf {
+  let base = env_path("XDG_CONFIG_HOME").unwrap_or_else(|| match env::consts::OS {
+    "macos" => home_dir().join("Library/Application Support"),
+    "windows" => env_path("APPDATA").unwrap_or_else(|| home_dir().join("AppData/Roaming")),
+    _ => home_dir().join(".config"),
+  });
+  base.join(APP_NAME)
+}
+
+/// Return the folder kicad-wakatime keeps its logs and other state in.
+/// This is `$XDG_STATE_HOME/kicad-wakatime`, or the usual per-OS location.
+pub fn state_dir() -> PathBuf {
+  let base = env_path("XDG_STATE_HOME").unwrap_or_else(|| match env::consts::OS {
+    "macos" => home_dir().join("Library/Application Support"),
+    "windows" => env_path("LOCALAPPDATA").unwrap_or_else(|| home_dir().join("AppData/Local")),
+    _ => home_dir().join(".local/state"),
+  });
+  base.join(APP_NAME)
+}
+
+/// Return the folder kicad-wakatime keeps files in that can be deleted at any time.
+/// This is `$XDG_CACHE_HOME/kicad-wakatime`, or the usual per-OS location.
+pub fn cache_dir() -> PathBuf {
+  if let Some(base) = env_path("XDG_CACHE_HOME") {
+    return base.join(APP_NAME)
+  }
+  match env::consts::OS {
+    "macos" => home_dir().join("Library/Caches").join(APP_NAME),
+    "windows" => state_dir().join("cache"),
+    _ => home_dir().join(".cache").join(APP_NAME),
+  }
+}
+
+/// Return the path to the .wakatime.cfg file.
+pub fn wakatime_cfg_path() -> PathBuf {
+  wakatime_home().join(".wakatime.cfg")
+}
+
+/// Return the path to the .wakatime folder, which the WakaTime CLI is kept in.
+pub fn wakatime_folder_path() -> PathBuf {
+  wakatime_home().join(".wakatime")
+}
+
+/// Return the path to kicad-wakatime's own config file.
+pub fn kicad_wakatime_cfg_path() -> PathBuf {
+  config_dir().join("kicad-wakatime.cfg")
+}
+
+/// Return the path to the kicad-wakatime log file.
+pub fn log_path() -> PathBuf {
+  state_dir().join("kicad-wakatime.log")
+}
+
+/// Return the path to the file the details of the last crash are saved to.
+pub fn crash_path() -> PathBuf {
+  state_dir().join("crash.txt")
+}
+
+/// Return the path to the history of heartbeats.
+pub fn history_path() -> PathBuf {
+  state_dir().join("history.jsonl")
+}
+
+/// Return the path to the file that says tracking is paused, and until when.
+pub fn pause_path() -> PathBuf {
+  state_dir().join("paused")
+}
+
+/// Return the path to the lock file held by the running kicad-wakatime.
+pub fn lock_path() -> PathBuf {
+  state_dir().join(format!("{APP_NAME}.lock"))
+}
+
+/// Return the path to the control socket of the running kicad-wakatime.
+/// This is in `XDG_RUNTIME_DIR` if it is set, since that is only readable by the user.
+pub fn control_socket_path() -> PathBuf {
+  match env_path("XDG_RUNTIME_DIR") {
+    Some(runtime_dir) => runtime_dir.join(format!("{APP_NAME}.sock")),
+    None => state_dir().join(format!("{APP_NAME}.sock")),
+  }
+}
+
+/// Return the path to the file with the port and token of the running kicad-wakatime's control
+/// server, on systems without Unix sockets.
+pub fn control_port_path() -> PathBuf {
+  state_dir().join(format!("{APP_NAME}.port"))
+}
+
+/// Create the config, state and cache folders, and move the dotfiles that older versions of
+/// kicad-wakatime kept in the home folder into them.
+/// Return a description of everything that was moved, to be logged once logging is set up.
+pub fn migrate_dotfiles() -> Result<Vec<String>, io::Error> {
+  fs::create_dir_all(config_dir())?;
+  fs::create_dir_all(state_dir())?;
+  fs::create_dir_all(cache_dir())?;
+  let mut moved = vec![];
+  let old_cfg_path = home_dir().join(".kicad-wakatime.cfg");
+  if old_cfg_path.is_file() && !kicad_wakatime_cfg_path().exists() {
+    move_file(&old_cfg_path, &kicad_wakatime_cfg_path())?;
+    moved.push(format!("Moved {:?} to {:?}", old_cfg_path, kicad_wakatime_cfg_path()));
+  }
+  // the old log is kept as the previous session's log
+  let old_log_path = home_dir().join(".kicad-wakatime.log");
+  if old_log_path.is_file() {
+    let previous_log_path = log_file::previous_log_path(&log_path(), 1);
+    move_file(&old_log_path, &previous_log_path)?;
+    moved.push(format!("Moved {:?} to {:?}", old_log_path, previous_log_path));
+  }
+  Ok(moved)
+}
+
+/// Move a file, even to another filesystem.
+fn move_file(from: &Path, to: &Path) -> Result<(), io::Error> {
+  if fs::rename(from, to).is_err() {
+    fs::copy(from, to)?;
+    fs::remove_file(from)?;
+  }
+  Ok(())
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, NaiveTime, Weekday};
+use log::info;
+
+use crate::{paths, Plugin};
+
+/// The longest pause that resumes by itself, in minutes (30 days).
+pub const MAX_PAUSE_MINUTES: u64 = 30 * 24 * 60;
+
+/// Tracking stopped by the user, saved to the state folder so that it lasts across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pause {
+  // when tracking starts again by itself, or None to wait for the user
+  pub until: Option<DateTime<Local>>,
+}
+
+impl Pause {
+  /// Load the pause saved by the last kicad-wakatime, if tracking is still paused.
+  pub fn load() -> Result<Option<Self>, anyhow::Error> {
+    let path = paths::pause_path();
+    if !path.is_file() {
+      return Ok(None)
+    }
+    let contents = fs::read_to_string(&path)?;
+    let until = match contents.trim() {
+      "" => None,
+      until => Some(DateTime::parse_from_rfc3339(until)
+        .with_context(|| format!("{:?} should contain a time, not {until:?}", path))?
+        .with_timezone(&Local)),
+    };
+    Ok(Some(Pause { until }))
+  }
+  pub fn save(&self) -> Result<(), io::Error> {
+    fs::create_dir_all(paths::state_dir())?;
+    fs::write(paths::pause_path(), self.until.map(|t| t.to_rfc3339()).unwrap_or_default())
+  }
+  pub fn clear() -> Result<(), io::Error> {
+    match fs::remove_file(paths::pause_path()) {
+      Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+      _ => Ok(()),
+    }
+  }
+  /// Return whether the pause has run out.
+  pub fn is_over(&self, now: DateTime<Local>) -> bool {
+    self.until.is_some_and(|until| now >= until)
+  }
+}
+
+/// A weekly time during which no heartbeats are sent, written like `mon-fri 18:00-09:00`,
+/// `sat,sun` or `* 12:00-13:00`. Times that end before they start (or when they start) run past
+/// midnight into the next day, so `mon-fri 18:00-09:00` covers Friday night but not Monday morning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuietHours {
+  // indexed by days from Monday
+  pub days: [bool; 7],
+  // the whole day if None
+  pub times: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl QuietHours {
+  /// Return whether a local time is within the quiet hours.
+  pub fn contains(&self, t: NaiveDateTime) -> bool {
+    let on = |day: Weekday| self.days[day.num_days_from_monday() as usize];
+    let time = t.time();
+    match self.times {
+      None => on(t.weekday()),
+      Some((start, end)) if start < end => on(t.weekday()) && time >= start && time < end,
+      Some((start, end)) => (on(t.weekday()) && time >= start) || (on(t.weekday().pred()) && time < end),
+    }
+  }
+}
+
+impl FromStr for QuietHours {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let error = || format!("quiet hours should look like \"mon-fri 18:00-09:00\", not {s:?}");
+    let (days_text, times_text) = match s.trim().split_once(' ') {
+      Some((days, times)) => (days, Some(times.trim())),
+      None => (s.trim(), None),
+    };
+    let mut days = [false; 7];
+    for part in days_text.split(',') {
+      if part == "*" {
+        days = [true; 7];
+        continue
+      }
+      let (first, last) = part.split_once('-').unwrap_or((part, part));
+      let first: Weekday = first.parse().map_err(|_| anyhow::anyhow!(error()))?;
+      let last: Weekday = last.parse().map_err(|_| anyhow::anyhow!(error()))?;
+      let mut day = first;
+      loop {
+        days[day.num_days_from_monday() as usize] = true;
+        if day == last {
+          break
+        }
+        day = day.succ();
+      }
+    }
+    let times = match times_text {
+      Some(times_text) => {
+        let (start, end) = times_text.split_once('-').with_context(error)?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").with_context(error)?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").with_context(error)?;
+        Some((start, end))
+      },
+      None => None,
+    };
+    Ok(QuietHours { days, times })
+  }
+}
+
+impl fmt::Display for QuietHours {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let days: Vec<String> = (0..7)
+      .filter(|&i| self.days[i])
+      .map(|i| Weekday::try_from(i as u8).unwrap().to_string().to_lowercase())
+      .collect();
+    if days.len() == 7 {
+      write!(f, "*")?;
+    } else {
+      write!(f, "{}", days.join(","))?;
+    }
+    if let Some((start, end)) = self.times {
+      write!(f, " {}-{}", start.format("%H:%M"), end.format("%H:%M"))?;
+    }
+    Ok(())
+  }
+}
+
+impl Plugin {
+  /// Stop sending heartbeats, for a number of minutes or until [`Plugin::resume`] is called.
+  pub fn pause(&mut self, minutes: Option<u64>) -> Result<(), anyhow::Error> {
+    let until = match minutes {
+      Some(minutes) => {
+        if minutes > MAX_PAUSE_MINUTES {
+          anyhow::bail!("Can't pause for more than {MAX_PAUSE_MINUTES} minutes, pause until resumed instead");
+        }
+        let length = Duration::try_minutes(minutes as i64).context("Pause is too long")?;
+        Some(Local::now().checked_add_signed(length).context("Pause is too long")?)
+      },
+      None => None,
+    };
+    let pause = Pause { until };
+    match pause.until {
+      Some(until) => info!("Paused until {}", until.format("%H:%M")),
+      None => info!("Paused"),
+    }
+    self.pause = Some(pause);
+    pause.save()?;
+    Ok(())
+  }
+  /// Start sending heartbeats again.
+  pub fn resume(&mut self) -> Result<(), anyhow::Error> {
+    if self.pause.take().is_some() {
+      info!("Resumed");
+    }
+    Pause::clear()?;
+    Ok(())
+  }
+  /// Pick up a pause from before kicad-wakatime was restarted.
+  pub fn load_pause(&mut self) -> Result<(), anyhow::Error> {
+    self.pause = Pause::load()?;
+    if let Some(pause) = self.pause {
+      match pause.until {
+        Some(until) => info!("Paused until {} (from before the restart)", until.format("%H:%M")),
+        None => info!("Paused (from before the restart)"),
+      }
+    }
+    self.check_pause()
+  }
+  /// Resume if the pause has run out.
+  pub fn check_pause(&mut self) -> Result<(), anyhow::Error> {
+    if self.pause.is_some_and(|p| p.is_over(Local::now())) {
+      self.resume()?;
+    }
+    Ok(())
+  }
+  /// Return whether it is quiet hours, going by the schedule in the settings.
+  pub fn in_quiet_hours(&self) -> bool {
+    let now = Local::now().naive_local();
+    self.config.quiet_hours.iter().any(|q| q.contains(now))
+  }
+  /// Return whether heartbeats are held back, because tracking is paused or it is quiet hours.
+  pub fn is_paused(&self) -> bool {
+    self.pause.is_some() || self.in_quiet_hours()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::NaiveDate;
+
+  use super::*;
+
+  // 2025-01-06 is a Monday
+  fn at(day: u32, time: &str) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2025, 1, day).unwrap()
+      .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+  }
+
+  #[test]
+  fn overnight_runs_into_next_day() {
+    let q: QuietHours = "mon-fri 18:00-09:00".parse().unwrap();
+    // Friday evening and the early hours of Saturday
+    assert!(q.contains(at(10, "18:00")));
+    assert!(q.contains(at(10, "23:59")));
+    assert!(q.contains(at(11, "00:00")));
+    assert!(q.contains(at(11, "08:59")));
+    // just outside
+    assert!(!q.contains(at(10, "17:59")));
+    assert!(!q.contains(at(11, "09:00")));
+    // Sunday night isn't quiet, so neither is Monday morning
+    assert!(!q.contains(at(12, "23:00")));
+    assert!(!q.contains(at(6, "08:00")));
+    assert!(q.contains(at(7, "08:00")));
+  }
+
+  #[test]
+  fn same_start_and_end_is_a_whole_day() {
+    // ends before it starts, so it runs from 12:00 to 12:00 the next day
+    let q: QuietHours = "wed 12:00-12:00".parse().unwrap();
+    assert!(!q.contains(at(8, "11:59")));
+    assert!(q.contains(at(8, "12:00")));
+    assert!(q.contains(at(9, "11:59")));
+    assert!(!q.contains(at(9, "12:00")));
+  }
+
+  #[test]
+  fn day_ranges_wrap_around_the_week() {
+    let q: QuietHours = "fri-mon".parse().unwrap();
+    assert_eq!(q.days, [true, false, false, false, true, true, true]);
+    assert!(q.contains(at(6, "12:00")));
+    assert!(!q.contains(at(7, "12:00")));
+    assert!(q.contains(at(10, "00:00")));
+    assert!(q.contains(at(12, "23:59")));
+  }
+
+  #[test]
+  fn round_trips_through_text() {
+    for text in ["mon-fri 18:00-09:00", "sat,sun", "* 12:00-13:00", "fri-mon 22:30-06:15", "tue,thu-sat"] {
+      let q: QuietHours = text.parse().unwrap();
+      assert_eq!(q.to_string().parse::<QuietHours>().unwrap(), q, "{text}");
+    }
+    assert_eq!("mon-wed".parse::<QuietHours>().unwrap().to_string(), "mon,tue,wed");
+    for text in ["", "weekends", "mon 9-17", "mon 09:00", "mon-fri 25:00-09:00"] {
+      assert!(text.parse::<QuietHours>().is_err(), "{text}");
+    }
+  }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+use anyhow::Context;
+use log::{debug, error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::backups::BackupIndex;
+use crate::project_config::ProjectConfig;
+use crate::prune::Pruner;
+
+/// A KiCAD project that kicad-wakatime is tracking.
+#[derive(Default)]
+pub struct Project {
+  // the .kicad_pro file
+  pub file: PathBuf,
+  pub folder: PathBuf,
+  // project name, as sent to WakaTime
+  pub name: String,
+  // settings from the .kicad-wakatime file next to the .kicad_pro file
+  pub config: ProjectConfig,
+  pub full_paths: HashMap<String, PathBuf>,
+  pub file_watcher: Option<RecommendedWatcher>,
+  // folder KiCAD writes backups of the project into
+  pub backups_folder: PathBuf,
+  // watches a backups folder outside the project folder
+  pub backups_watcher: Option<RecommendedWatcher>,
+  // the folder it watches, the nearest one that exists until KiCAD creates the backups folder
+  pub backups_watched: Option<PathBuf>,
+  pub backup_index: BackupIndex,
+  // the time a new backup was last seen, if it has not been looked at yet
+  pub backup_created_time: Option<Duration>,
+  pub pruner: Pruner,
+}
+
+impl Project {
+  pub fn new(file: PathBuf) -> Self {
+    let folder = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    Project {
+      file,
+      folder,
+      name,
+      ..Default::default()
+    }
+  }
+  /// Start watching the project and its backups for changes.
+  /// `backup_path` is the backup folder configured in KiCAD, if any.
+  pub fn watch(
+    &mut self,
+    tx: Sender<notify::Result<notify::Event>>,
+    backup_path: Option<PathBuf>
+  ) -> Result<(), anyhow::Error> {
+    self.load_config();
+    info!("Watching {:?} for changes", self.folder);
+    let mut file_watcher = notify::recommended_watcher(tx.clone())?;
+    file_watcher.watch(self.folder.as_path(), RecursiveMode::Recursive)?;
+    self.backups_folder = match backup_path {
+      // join() keeps absolute paths as they are
+      Some(backup_path) => self.folder.join(backup_path),
+      None => self.folder.join(format!("{}-backups", self.stem())),
+    };
+    info!("Backups folder: {:?}", self.backups_folder);
+    // the project folder is already watched recursively, but the backups folder may be elsewhere
+    if !self.backups_folder.starts_with(&self.folder) {
+      if let Err(e) = self.watch_backups_folder(tx) {
+        warn!("Could not watch {:?}, new backups won't be seen: {e:#}", self.backups_folder);
+      }
+    }
+    self.file_watcher = Some(file_watcher);
+    // the backups folder will not exist until KiCAD makes the first backup
+    self.backup_index = BackupIndex::load(&self.backups_folder).unwrap_or_default();
+    debug!("{} backups found", self.backup_index.len());
+    self.full_paths = HashMap::new();
+    self.recursively_add_full_paths(self.folder.clone())?;
+    debug!("full_paths = {:?}", self.full_paths);
+    Ok(())
+  }
+  /// Watch a backups folder outside the project folder. It is not created here, since it may be
+  /// on a drive that isn't there yet; until KiCAD creates it, the nearest folder above it is watched.
+  fn watch_backups_folder(&mut self, tx: Sender<notify::Result<notify::Event>>) -> Result<(), anyhow::Error> {
+    let backups_folder = self.backups_folder.clone();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+      // the folder above can be busy, only pass on what happens on the way to the backups folder
+      let on_the_way = |path: &PathBuf| path.starts_with(&backups_folder) || backups_folder.starts_with(path);
+      if event.as_ref().is_ok_and(|e| e.paths.iter().any(on_the_way)) {
+        let _ = tx.send(event);
+      }
+    })?;
+    self.backups_watcher = Some(watcher);
+    self.update_backups_watch()
+  }
+  /// Move the watch closer to the backups folder, after folders on the way to it were created.
+  pub fn update_backups_watch(&mut self) -> Result<(), anyhow::Error> {
+    let Some(ref mut watcher) = self.backups_watcher else { return Ok(()) };
+    let folder = self.backups_folder.ancestors()
+      .find(|folder| folder.is_dir())
+      .with_context(|| format!("No folder on the way to {:?} exists", self.backups_folder))?
+      .to_path_buf();
+    if self.backups_watched.as_ref() == Some(&folder) {
+      return Ok(())
+    }
+    if let Some(ref watched) = self.backups_watched {
+      let _ = watcher.unwatch(watched);
+    }
+    info!("Watching {:?} for changes", folder);
+    watcher.watch(&folder, RecursiveMode::NonRecursive)?;
+    // KiCAD may have written the first backup before the folder was watched
+    if self.backups_watched.is_some() && folder == self.backups_folder {
+      self.backup_index = BackupIndex::load(&self.backups_folder).unwrap_or_default();
+    }
+    self.backups_watched = Some(folder);
+    Ok(())
+  }
+  /// Load the project's .kicad-wakatime file, which is merged over the user's settings.
+  pub fn load_config(&mut self) {
+    self.config = ProjectConfig::load(&self.folder).unwrap_or_else(|e| {
+      error!("{:?}", e);
+      ProjectConfig::default()
+    });
+    self.name = match self.config.name {
+      Some(ref name) => name.clone(),
+      None => self.stem(),
+    };
+  }
+  /// Return the file stem of the .kicad_pro file, which is what KiCAD shows in window titles.
+  pub fn stem(&self) -> String {
+    self.file.file_stem().unwrap_or_default().to_string_lossy().to_string()
+  }
+  /// Return the path of a file relative to the project folder, with `/` as the separator.
+  pub fn relative_path(&self, path: &Path) -> Option<String> {
+    let relative_path = path.strip_prefix(&self.folder).ok()?;
+    Some(relative_path.components()
+      .map(|c| c.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/"))
+  }
+  pub fn get_full_path(&self, filename: &str) -> Option<&PathBuf> {
+    self.full_paths.get(filename)
+  }
+  /// Return whether a path is a backup of this project.
+  pub fn is_backup(&self, path: &Path) -> bool {
+    path.parent() == Some(self.backups_folder.as_path())
+  }
+  /// Return whether a path belongs to this project.
+  pub fn contains(&self, path: &Path) -> bool {
+    path.starts_with(&self.folder) || self.is_backup(path)
+  }
+  /// Return the names of schematic and PCB files that appear more than once in the project folder.
+  /// kicad-wakatime only knows the file name from the window title, so these can't be told apart.
+  pub fn duplicate_file_names(&self) -> Result<Vec<String>, anyhow::Error> {
+    fn count(path: &Path, counts: &mut HashMap<String, usize>) -> Result<(), anyhow::Error> {
+      for path in fs::read_dir(path)? {
+        let path = path?.path();
+        if path.is_dir() {
+          count(&path, counts)?;
+        } else if path.extension().is_some_and(|e| e == "kicad_sch" || e == "kicad_pcb") {
+          let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+          *counts.entry(file_name).or_default() += 1;
+        }
+      }
+      Ok(())
+    }
+    let mut counts = HashMap::new();
+    count(&self.folder, &mut counts)?;
+    let mut duplicates: Vec<String> = counts.into_iter()
+      .filter(|(_, count)| *count > 1)
+      .map(|(file_name, _)| file_name)
+      .collect();
+    duplicates.sort();
+    Ok(duplicates)
+  }
+  pub fn recursively_add_full_paths(&mut self, path: PathBuf) -> Result<(), anyhow::Error> {
+    for path in fs::read_dir(path)? {
+      let path = path?.path();
+      if path.is_dir() { self.recursively_add_full_paths(path.clone())?; };
+      if !path.is_file() { continue; };
+      let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else { continue; };
+      let Some(file_extension) = path.extension().and_then(|e| e.to_str()) else { continue; };
+      if file_extension == "kicad_sch" || file_extension == "kicad_pcb" {
+        if self.full_paths.contains_key(file_name) {
+          error!("Found multiple files named {file_name} in {:?}!", self.folder);
+          error!("Please select a project whose folder only contains one file named {file_name}!");
+          self.full_paths = HashMap::new();
+          return Ok(())
+        }
+        self.full_paths.insert(
+          file_name.to_string(),
+          path
+        );
+      }
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,214 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use ini::Ini;
+use log::warn;
+use regex::Regex;
+
+/// Name of the optional project config file, kept next to the .kicad_pro file
+/// so that it can be committed with the project.
+pub const PROJECT_CONFIG_NAME: &str = ".kicad-wakatime";
+
+/// The categories the WakaTime CLI accepts for `--category`.
+pub const CATEGORIES: &[&str] = &[
+  "coding",
+  "building",
+  "indexing",
+  "debugging",
+  "running tests",
+  "writing tests",
+  "manual testing",
+  "writing docs",
+  "code reviewing",
+  "communicating",
+  "researching",
+  "learning",
+  "designing",
+  "browsing",
+  "meeting",
+  "planning",
+  "supporting",
+  "translating",
+  "ai coding",
+];
+
+/// Settings shared by everyone working on a project, from the project's .kicad-wakatime file.
+///
+/// ```ini
+/// [project]
+/// name = Main Board
+///
+/// [libraries]
+/// ; relative to the project folder
+/// symbol_file = libs/lab.kicad_sym
+/// footprint_folder = libs/lab.pretty
+///
+/// [categories]
+/// ; first matching pattern wins
+/// *.kicad_pcb = designing
+/// test/** = debugging
+///
+/// [ignore]
+/// pattern = scratch/**
+/// ```
+#[derive(Debug, Default)]
+pub struct ProjectConfig {
+  // project name sent to WakaTime, instead of the .kicad_pro file stem
+  pub name: Option<String>,
+  pub symbol_files: Vec<PathBuf>,
+  pub footprint_folders: Vec<PathBuf>,
+  // WakaTime category for files matching each pattern
+  pub categories: Vec<(Pattern, String)>,
+  // files that never get heartbeats
+  pub ignore: Vec<Pattern>,
+}
+
+impl ProjectConfig {
+  /// Load the project config from a project folder. A missing file is an empty config.
+  pub fn load(project_folder: &Path) -> Result<Self, anyhow::Error> {
+    let path = project_folder.join(PROJECT_CONFIG_NAME);
+    if !fs::exists(&path)? {
+      return Ok(ProjectConfig::default())
+    }
+    let ini = Ini::load_from_file(&path).with_context(|| format!("Could not parse {:?}", path))?;
+    let paths = |key: &str| -> Vec<PathBuf> {
+      ini.section(Some("libraries"))
+        .into_iter()
+        .flat_map(|s| s.get_all(key))
+        .filter(|value| !value.is_empty())
+        .map(|value| project_folder.join(value))
+        .collect()
+    };
+    let categories = ini.section(Some("categories"))
+      .into_iter()
+      .flat_map(|s| s.iter())
+      // the WakaTime CLI would reject every heartbeat with an unknown category
+      .filter(|(pattern, category)| {
+        let valid = CATEGORIES.contains(category);
+        if !valid {
+          warn!("Ignoring category {category:?} for {pattern:?} in {:?}, it should be one of {}", path, CATEGORIES.join(", "));
+        }
+        valid
+      })
+      .map(|(pattern, category)| Ok((Pattern::new(pattern)?, category.to_string())))
+      .collect::<Result<_, anyhow::Error>>()?;
+    let ignore = ini.section(Some("ignore"))
+      .into_iter()
+      .flat_map(|s| s.get_all("pattern"))
+      .map(Pattern::new)
+      .collect::<Result<_, _>>()?;
+    Ok(ProjectConfig {
+      name: ini.get_from(Some("project"), "name").filter(|n| !n.is_empty()).map(str::to_string),
+      symbol_files: paths("symbol_file"),
+      footprint_folders: paths("footprint_folder"),
+      categories,
+      ignore,
+    })
+  }
+  /// Return the category for a file, given its path relative to the project folder.
+  pub fn category(&self, relative_path: &str) -> Option<&str> {
+    self.categories.iter()
+      .find(|(pattern, _)| pattern.matches(relative_path))
+      .map(|(_, category)| category.as_str())
+  }
+  /// Return whether a file should never get heartbeats, given its path relative to the project folder.
+  pub fn is_ignored(&self, relative_path: &str) -> bool {
+    self.ignore.iter().any(|pattern| pattern.matches(relative_path))
+  }
+}
+
+/// A glob pattern matched against paths relative to the project folder, using `/` as the separator.
+/// `*` and `?` do not match `/`, `**` matches anything, and `**/` matches any number of folders,
+/// including none, so `**/scratch.kicad_sch` matches in the project folder too.
+/// A pattern without `/` also matches the file name alone, so `*.kicad_pcb` matches in any folder.
+#[derive(Debug)]
+pub struct Pattern {
+  glob: String,
+  re: Regex,
+}
+
+impl Pattern {
+  pub fn new(glob: &str) -> Result<Self, anyhow::Error> {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+      match c {
+        '*' if chars.peek() == Some(&'*') => {
+          chars.next();
+          if chars.peek() == Some(&'/') {
+            chars.next();
+            re.push_str("(?:.*/)?");
+          } else {
+            re.push_str(".*");
+          }
+        },
+        '*' => re.push_str("[^/]*"),
+        '?' => re.push_str("[^/]"),
+        c => re.push_str(&regex::escape(&c.to_string())),
+      }
+    }
+    re.push('$');
+    let re = Regex::new(&re).with_context(|| format!("Invalid pattern {glob:?}"))?;
+    Ok(Pattern { glob: glob.to_string(), re })
+  }
+  pub fn matches(&self, relative_path: &str) -> bool {
+    let relative_path = relative_path.replace('\\', "/");
+    if self.re.is_match(&relative_path) {
+      return true
+    }
+    !self.glob.contains('/') && relative_path.rsplit('/').next().is_some_and(|n| self.re.is_match(n))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn load(contents: &str) -> ProjectConfig {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    fs::write(dir.join(PROJECT_CONFIG_NAME), contents).unwrap();
+    ProjectConfig::load(dir).unwrap()
+  }
+
+  #[test]
+  fn ignores_matching_files() {
+    let config = load("[ignore]\npattern = scratch/**\npattern = **/old-*.kicad_sch\npattern = *.bak\n");
+    assert!(config.is_ignored("scratch/test.kicad_sch"));
+    assert!(config.is_ignored("scratch/deeper/test.kicad_pcb"));
+    assert!(!config.is_ignored("boards/scratch/test.kicad_sch"));
+    // **/ matches no folder too
+    assert!(config.is_ignored("old-power.kicad_sch"));
+    assert!(config.is_ignored("sheets/v1/old-power.kicad_sch"));
+    assert!(!config.is_ignored("sheets/bold-power.kicad_sch"));
+    // a pattern without / matches the file name in any folder
+    assert!(config.is_ignored("main.kicad_pcb.bak"));
+    assert!(config.is_ignored("sheets\\power.kicad_sch.bak"));
+    assert!(!config.is_ignored("main.kicad_pcb"));
+    assert!(!ProjectConfig::default().is_ignored("main.kicad_pcb"));
+  }
+
+  #[test]
+  fn picks_first_matching_category() {
+    let config = load("\
+      [categories]\n\
+      test/** = debugging\n\
+      *.kicad_pcb = designing\n\
+      *.kicad_sch = drawing\n\
+      **/*.kicad_sch = planning\n");
+    assert_eq!(config.category("test/probe.kicad_pcb"), Some("debugging"));
+    assert_eq!(config.category("main.kicad_pcb"), Some("designing"));
+    assert_eq!(config.category("boards/io.kicad_pcb"), Some("designing"));
+    // "drawing" is not a WakaTime category, so the next pattern is used
+    assert_eq!(config.category("main.kicad_sch"), Some("planning"));
+    assert_eq!(config.categories.len(), 3);
+    assert_eq!(config.category("main.kicad_pro"), None);
+  }
+
+  #[test]
+  fn missing_file_is_empty_config() {
+    let config = ProjectConfig::load(tempfile::tempdir().unwrap().path()).unwrap();
+    assert!(config.name.is_none() && config.categories.is_empty() && config.ignore.is_empty());
+  }
+}
@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::info;
+
+use crate::backups::{self, BackupIndex};
+
+/// The newest backups are never pruned, because change detection compares them.
+pub const PROTECTED_BACKUPS: usize = 2;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+const WEEK: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Whether backups are pruned after each new one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PruneMode {
+  #[default]
+  Off,
+  /// Log what would be deleted, but do not delete anything.
+  DryRun,
+  On,
+}
+
+impl FromStr for PruneMode {
+  type Err = anyhow::Error;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "" | "off" => Ok(PruneMode::Off),
+      "dry-run" => Ok(PruneMode::DryRun),
+      "on" => Ok(PruneMode::On),
+      _ => Err(anyhow::anyhow!("Unknown prune mode {s:?} (expected off, dry-run or on)")),
+    }
+  }
+}
+
+impl fmt::Display for PruneMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      PruneMode::Off => "off",
+      PruneMode::DryRun => "dry-run",
+      PruneMode::On => "on",
+    })
+  }
+}
+
+/// How many backups to keep, depending on their age.
+/// Every backup younger than `keep_all` is kept, then one per hour until `hourly`,
+/// one per day until `daily`, and one per week after that.
+#[derive(Debug, Clone, Copy)]
+pub struct RetentionPolicy {
+  pub keep_all: Duration,
+  pub hourly: Duration,
+  pub daily: Duration,
+}
+
+impl Default for RetentionPolicy {
+  fn default() -> Self {
+    RetentionPolicy {
+      keep_all: HOUR,
+      hourly: DAY,
+      daily: 30 * DAY,
+    }
+  }
+}
+
+impl RetentionPolicy {
+  /// Return the bucket a backup falls into, or `None` if it is young enough to always keep.
+  /// Only the newest backup in each bucket is kept.
+  fn bucket(&self, time: SystemTime, now: SystemTime) -> Option<(u8, u64)> {
+    let age = now.duration_since(time).unwrap_or_default();
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if age < self.keep_all {
+      None
+    } else if age < self.hourly {
+      Some((0, since_epoch / HOUR.as_secs()))
+    } else if age < self.daily {
+      Some((1, since_epoch / DAY.as_secs()))
+    } else {
+      Some((2, since_epoch / WEEK.as_secs()))
+    }
+  }
+}
+
+/// Why a backup is going to be deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneReason {
+  /// Every file in the backup is the same as in the one before it.
+  Redundant,
+  /// A newer backup covers the same period of the retention policy.
+  Retention,
+}
+
+/// The backups that pruning would delete.
+#[derive(Debug, Default)]
+pub struct PrunePlan {
+  pub delete: Vec<(PathBuf, PruneReason)>,
+}
+
+/// Decides which backups to prune.
+/// Comparing a backup with the one before it is remembered, so each backup is only opened once.
+#[derive(Debug, Default)]
+pub struct Pruner {
+  pub policy: RetentionPolicy,
+  redundant: HashMap<PathBuf, bool>,
+}
+
+impl Pruner {
+  /// Work out which backups to delete, never touching the newest ones.
+  pub fn plan(&mut self, index: &BackupIndex, now: SystemTime) -> Result<PrunePlan, anyhow::Error> {
+    let backups = index.backups();
+    let mut plan = PrunePlan::default();
+    if backups.len() <= PROTECTED_BACKUPS {
+      return Ok(plan)
+    }
+    let candidates = &backups[..backups.len() - PROTECTED_BACKUPS];
+    // redundant backups first...
+    let mut previous: Option<&PathBuf> = None;
+    for (_, path) in candidates {
+      if let Some(previous) = previous {
+        let redundant = match self.redundant.get(path) {
+          Some(redundant) => *redundant,
+          None => {
+            let redundant = backups::backups_identical(previous, path)?;
+            self.redundant.insert(path.clone(), redundant);
+            redundant
+          },
+        };
+        if redundant {
+          plan.delete.push((path.clone(), PruneReason::Redundant));
+          // the next backup is still compared against the last one that is kept
+          continue
+        }
+      }
+      previous = Some(path);
+    }
+    // ...then whatever the retention policy does not need, newest first
+    let mut seen_buckets = HashSet::new();
+    for (time, path) in backups.iter().rev() {
+      let Some(bucket) = self.policy.bucket(*time, now) else { continue };
+      let is_candidate = candidates.iter().any(|(_, p)| p == path);
+      let is_redundant = plan.delete.iter().any(|(p, _)| p == path);
+      if is_redundant {
+        continue
+      }
+      if !seen_buckets.insert(bucket) && is_candidate {
+        plan.delete.push((path.clone(), PruneReason::Retention));
+      }
+    }
+    Ok(plan)
+  }
+  /// Delete the backups in a plan, or only log them if `dry_run` is set.
+  pub fn execute(
+    &mut self,
+    plan: &PrunePlan,
+    index: &mut BackupIndex,
+    dry_run: bool
+  ) -> Result<(), anyhow::Error> {
+    for (path, reason) in &plan.delete {
+      if dry_run {
+        info!("Would prune {:?} ({:?})", path, reason);
+        continue
+      }
+      info!("Pruning {:?} ({:?})", path, reason);
+      fs::remove_file(path)?;
+      index.remove(path);
+      self.redundant.remove(path);
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::File;
+  use std::io::Write;
+  use std::path::Path;
+  use zip::write::SimpleFileOptions;
+  use zip::ZipWriter;
+
+  use super::*;
+
+  fn write_backup(dir: &Path, name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let path = dir.join(name);
+    let mut zip = ZipWriter::new(File::create(&path).unwrap());
+    for (file_name, contents) in files {
+      zip.start_file(*file_name, SimpleFileOptions::default()).unwrap();
+      zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+    path
+  }
+
+  // a time on an hour boundary, so that the retention buckets are predictable
+  fn now() -> SystemTime {
+    UNIX_EPOCH + 1000 * DAY
+  }
+
+  fn deleted(plan: &PrunePlan) -> Vec<(String, PruneReason)> {
+    plan.delete.iter()
+      .map(|(path, reason)| (path.file_name().unwrap().to_string_lossy().to_string(), *reason))
+      .collect()
+  }
+
+  #[test]
+  fn detects_redundant_backups() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut index = BackupIndex::default();
+    let backups = [
+      ("0.zip", vec![("board.kicad_pcb", "a"), ("board.kicad_pro", "x")]),
+      ("1.zip", vec![("board.kicad_pcb", "a"), ("board.kicad_pro", "x")]),
+      // only the project settings changed, which must not be lost
+      ("2.zip", vec![("board.kicad_pcb", "a"), ("board.kicad_pro", "y")]),
+      // a new library table
+      ("3.zip", vec![("board.kicad_pcb", "a"), ("board.kicad_pro", "y"), ("fp-lib-table", "z")]),
+      ("4.zip", vec![("board.kicad_pcb", "b")]),
+      ("5.zip", vec![("board.kicad_pcb", "c")]),
+    ];
+    for (i, (name, files)) in backups.iter().enumerate() {
+      let path = write_backup(dir, name, files);
+      index.insert_at(path, now() - Duration::from_secs(60 * (10 - i as u64)));
+    }
+    let plan = Pruner::default().plan(&index, now()).unwrap();
+    assert_eq!(deleted(&plan), vec![(String::from("1.zip"), PruneReason::Redundant)]);
+  }
+
+  #[test]
+  fn keeps_newest_backup_per_bucket() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut index = BackupIndex::default();
+    let ages = [
+      ("weekly-old.zip", 60 * DAY + HOUR),
+      ("weekly-new.zip", 60 * DAY),
+      ("daily-old.zip", 10 * DAY + 2 * HOUR),
+      ("daily-new.zip", 10 * DAY + HOUR),
+      ("hourly-old.zip", 3 * HOUR + Duration::from_secs(40 * 60)),
+      ("hourly-new.zip", 3 * HOUR + Duration::from_secs(20 * 60)),
+      ("recent-1.zip", Duration::from_secs(30 * 60)),
+      ("recent-2.zip", Duration::from_secs(20 * 60)),
+      ("newest-1.zip", Duration::from_secs(10 * 60)),
+      ("newest-2.zip", Duration::from_secs(5 * 60)),
+    ];
+    for (i, (name, age)) in ages.iter().enumerate() {
+      // every backup is different, so only the retention policy deletes anything
+      let contents = i.to_string();
+      let path = write_backup(dir, name, &[("board.kicad_pcb", &contents)]);
+      index.insert_at(path, now() - *age);
+    }
+    let plan = Pruner::default().plan(&index, now()).unwrap();
+    let mut deleted = deleted(&plan);
+    deleted.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(deleted, vec![
+      (String::from("daily-old.zip"), PruneReason::Retention),
+      (String::from("hourly-old.zip"), PruneReason::Retention),
+      (String::from("weekly-old.zip"), PruneReason::Retention),
+    ]);
+  }
+
+  #[test]
+  fn never_prunes_newest_backups() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut index = BackupIndex::default();
+    for (i, name) in ["0.zip", "1.zip", "2.zip"].iter().enumerate() {
+      // identical and all in the same old bucket
+      let path = write_backup(dir, name, &[("board.kicad_pcb", "a")]);
+      index.insert_at(path, now() - 40 * DAY + Duration::from_secs(i as u64));
+    }
+    // the newest two are identical and share a bucket with the oldest, but only the oldest goes
+    let plan = Pruner::default().plan(&index, now()).unwrap();
+    assert_eq!(deleted(&plan), vec![(String::from("0.zip"), PruneReason::Retention)]);
+    // with only the protected backups there is nothing to do
+    index.remove(&dir.join("0.zip"));
+    let plan = Pruner::default().plan(&index, now()).unwrap();
+    assert!(plan.delete.is_empty());
+  }
+
+  #[test]
+  fn dry_run_deletes_nothing() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut index = BackupIndex::default();
+    for (i, name) in ["0.zip", "1.zip", "2.zip", "3.zip"].iter().enumerate() {
+      let path = write_backup(dir, name, &[("board.kicad_pcb", if i == 3 { "b" } else { "a" })]);
+      index.insert_at(path, now() - Duration::from_secs(60 * (10 - i as u64)));
+    }
+    let mut pruner = Pruner::default();
+    let plan = pruner.plan(&index, now()).unwrap();
+    assert_eq!(deleted(&plan), vec![(String::from("1.zip"), PruneReason::Redundant)]);
+    pruner.execute(&plan, &mut index, true).unwrap();
+    assert!(dir.join("1.zip").exists());
+    assert_eq!(index.len(), 4);
+    pruner.execute(&plan, &mut index, false).unwrap();
+    assert!(!dir.join("1.zip").exists());
+    assert_eq!(index.len(), 3);
+    assert!(pruner.plan(&index, now()).unwrap().delete.is_empty());
+  }
+}
@@ -0,0 +1,63 @@
+use std::sync::{LazyLock, RwLock};
+use log::{Log, Metadata, Record};
+use regex::Regex;
+
+// secrets to remove from log messages, in addition to anything that looks like an API key
+static SECRETS: RwLock<Vec<String>> = RwLock::new(vec![]);
+
+/// Remove a secret (e.g. an API key that does not look like one) from all future log messages.
+pub fn add_secret(secret: &str) {
+  // short strings would match too much
+  if secret.len() < 8 {
+    return
+  }
+  let mut secrets = SECRETS.write().unwrap();
+  if !secrets.iter().any(|s| s == secret) {
+    secrets.push(secret.to_string());
+  }
+}
+
+/// Replace API keys and other secrets in a message.
+pub fn scrub(message: &str) -> String {
+  static RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(waka_)?[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}").unwrap()
+  });
+  let mut message = RE.replace_all(message, "<redacted>").to_string();
+  for secret in SECRETS.read().unwrap().iter() {
+    message = message.replace(secret, "<redacted>");
+  }
+  message
+}
+
+/// A logger that removes secrets from messages before passing them on.
+pub struct Scrubber {
+  inner: Box<dyn Log>,
+}
+
+impl Scrubber {
+  pub fn new(inner: Box<dyn Log>) -> Self {
+    Scrubber { inner }
+  }
+}
+
+impl Log for Scrubber {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    self.inner.enabled(metadata)
+  }
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return
+    }
+    let message = scrub(&record.args().to_string());
+    self.inner.log(&Record::builder()
+      .metadata(record.metadata().clone())
+      .args(format_args!("{message}"))
+      .module_path(record.module_path())
+      .file(record.file())
+      .line(record.line())
+      .build());
+  }
+  fn flush(&self) {
+    self.inner.flush();
+  }
+}
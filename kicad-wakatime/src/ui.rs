@@ -1,27 +1,33 @@
-use std::path::PathBuf;
-
 use eframe::egui::{self, Color32, RichText};
 use egui_modal::Modal;
 // use log::debug;
+use log::error;
 
+use crate::connection::{ConnectionTest, DEFAULT_API_URL};
+use crate::pause::QuietHours;
+use crate::prune::PruneMode;
 use crate::Plugin;
 
+// choices in the pause menu, in minutes
+const PAUSE_LENGTHS: &[(&str, Option<u64>)] = &[
+  ("for 15 minutes", Some(15)),
+  ("for 1 hour", Some(60)),
+  ("for 4 hours", Some(4 * 60)),
+  ("until I resume", None),
+];
+
 pub trait Ui {
   fn draw_ui(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) -> Result<(), anyhow::Error>;
 }
 
 impl Ui for Plugin {
   fn draw_ui(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) -> Result<(), anyhow::Error> {
-    let projects_file = self.get_projects_file();
-    let api_key = self.get_api_key();
-    let api_url = self.get_api_url();
-    let status = if !self.first_iteration_finished {
-      "loading..."
-    } else if projects_file.as_os_str().is_empty() || api_key.is_empty() || api_url.is_empty() {
-      "need settings!"
-    } else {
-      "OK"
-    };
+    let status = self.status();
+    if self.focus_requested {
+      self.focus_requested = false;
+      ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+      ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+    }
     let last_heartbeat_label_text = match self.last_sent_time_chrono {
       Some(dt) => dt.format("%H:%M:%S").to_string(),
       None => String::from("N/A"),
@@ -30,66 +36,244 @@ impl Ui for Plugin {
     let modal = Modal::new(ctx, "settings");
     // luckily this call has a generic for the return type!
     modal.show(|ui| -> Result<(), anyhow::Error> {
+      let config = &mut self.config_draft;
       ui.label(RichText::new("kicad-wakatime settings ^w^").size(16.0));
       ui.add_space(10.0);
-      ui.label("KiCAD project:");
+      ui.label("KiCAD projects:");
       // ui.text_edit_singleline(&mut self.watched_folder);
-      ui.monospace(format!("{:?}", self.projects_file));
-      if ui.button("select .kicad_pro file").clicked() {
+      let mut removed = None;
+      for (i, projects_file) in config.projects_files.iter().enumerate() {
+        ui.horizontal(|ui| {
+          ui.monospace(format!("{:?}", projects_file));
+          if ui.button("remove").clicked() {
+            removed = Some(i);
+          }
+        });
+      }
+      if let Some(i) = removed {
+        config.projects_files.remove(i);
+      }
+      ui.checkbox(&mut config.auto_track, "automatically track the projects open in KiCAD");
+      if ui.button("add .kicad_pro file").clicked() {
         if let Some(path) = rfd::FileDialog::new().add_filter("Kicad Project", &["kicad_pro"]).pick_file() {
-          self.projects_file = path.to_str().unwrap().to_string();
-          self.projects_folder = path.parent().expect("Why not").to_str().unwrap().to_string();
+          if !config.projects_files.contains(&path) {
+            config.projects_files.push(path);
+          }
         }
       }
 
       // For footprint and symbol editing
       ui.label("Symbol Library File:");
-      ui.monospace(format!("{:?}", self.symbol));
+      ui.monospace(format!("{:?}", config.symbol_file.clone().unwrap_or_default()));
       if ui.button("select .kicad_sym file").clicked() {
         if let Some(path) = rfd::FileDialog::new().add_filter("Kicad Symbol", &["kicad_sym"]).pick_file() {
-          self.symbol = path.to_str().unwrap().to_string();
+          config.symbol_file = Some(path);
         }
       }
       ui.label("Footprint Library Path:");
-      ui.monospace(format!("{:?}", self.footprint));
+      ui.monospace(format!("{:?}", config.footprint_folder.clone().unwrap_or_default()));
       if ui.button("select folder containing .kicad_mod files").clicked() {
         if let Some(path) = rfd::FileDialog::new().pick_folder() {
-          self.footprint = path.to_str().unwrap().to_string();
+          config.footprint_folder = Some(path);
         }
       }
 
       ui.label("API key:");
-      ui.text_edit_singleline(&mut self.api_key);
+      text_edit_optional(ui, &mut config.api_key);
       ui.label("API URL:");
-      ui.text_edit_singleline(&mut self.api_url);
+      text_edit_optional(ui, &mut config.api_url);
+      ui.horizontal(|ui| {
+        let running = self.connection_test.as_mut().is_some_and(|test| test.is_running());
+        if ui.add_enabled(!running, egui::Button::new("test connection")).clicked() {
+          let api_key = config.api_key.clone().unwrap_or_default();
+          let api_url = config.api_url.clone().unwrap_or(String::from(DEFAULT_API_URL));
+          self.connection_test = Some(ConnectionTest::start(api_key, api_url));
+        }
+        match self.connection_test.as_ref().and_then(|test| test.result.as_ref()) {
+          _ if running => { ui.label("testing..."); },
+          Some(Ok(Some(username))) => { ui.colored_label(Color32::GREEN, format!("connected as {username}")); },
+          Some(Ok(None)) => { ui.colored_label(Color32::GREEN, "connected"); },
+          Some(Err(e)) => { ui.colored_label(Color32::RED, e); },
+          None => {},
+        }
+      });
+      ui.label("Prune redundant backups:");
+      ui.horizontal(|ui| {
+        ui.radio_value(&mut config.prune_mode, PruneMode::Off, "off");
+        ui.radio_value(&mut config.prune_mode, PruneMode::DryRun, "dry run (log only)");
+        ui.radio_value(&mut config.prune_mode, PruneMode::On, "on");
+      });
+      ui.horizontal(|ui| {
+        ui.label("Stop counting time after being idle for:");
+        let mut minutes = config.idle_timeout().map(|t| t.as_secs().div_ceil(60)).unwrap_or(0);
+        if ui.add(egui::DragValue::new(&mut minutes).range(0..=120).suffix(" min")).changed() {
+          config.idle_timeout = Some(minutes * 60);
+        }
+        if minutes == 0 {
+          ui.label("(never)");
+        }
+      });
+      ui.horizontal(|ui| {
+        ui.label("While working on one document, send a heartbeat every:");
+        let mut minutes = config.keep_alive_interval().map(|t| t.as_secs().div_ceil(60)).unwrap_or(0);
+        if ui.add(egui::DragValue::new(&mut minutes).range(0..=60).suffix(" min")).changed() {
+          config.keep_alive_interval = Some(minutes * 60);
+        }
+        if minutes == 0 {
+          ui.label("(only on focus changes and saves)");
+        }
+      });
+      ui.label("Quiet hours (no heartbeats are sent):");
+      let mut removed = None;
+      for (i, quiet_hours) in config.quiet_hours.iter().enumerate() {
+        ui.horizontal(|ui| {
+          ui.monospace(quiet_hours.to_string());
+          if ui.button("remove").clicked() {
+            removed = Some(i);
+          }
+        });
+      }
+      if let Some(i) = removed {
+        config.quiet_hours.remove(i);
+      }
+      ui.horizontal(|ui| {
+        ui.add(egui::TextEdit::singleline(&mut self.quiet_hours_draft).hint_text("mon-fri 18:00-09:00"));
+        if ui.button("add").clicked() {
+          match self.quiet_hours_draft.parse::<QuietHours>() {
+            Ok(quiet_hours) => {
+              config.quiet_hours.push(quiet_hours);
+              self.quiet_hours_draft.clear();
+            },
+            Err(e) => error!("{e}"),
+          }
+        }
+      });
+      for problem in self.draft_validation.problems(config) {
+        ui.colored_label(Color32::YELLOW, problem.to_string());
+      }
+      let overridden = self.overrides.overridden();
+      if !overridden.is_empty() {
+        ui.label(format!(
+          "Set from the command line or environment, changes will not be saved: {}",
+          overridden.join(", ")
+        ));
+      }
 
       if ui.button("OK").clicked() {
-        self.set_projects_file(self.projects_file.clone());
-        self.set_symbol_file(self.symbol.clone());
-        self.set_footprint_folder(self.footprint.clone());
-        self.set_api_key(self.api_key.clone());
-        self.set_api_url(self.api_url.clone());
+        self.config = self.config_draft.clone();
         self.store_config()?;
-        self.watch_files(PathBuf::from(self.projects_folder.clone()))?;
+        self.watch_projects()?;
         modal.close();
       }
       Ok(())
     });
     // main window
-    egui::CentralPanel::default().show(ctx, |ui| {
+    egui::CentralPanel::default().show(ctx, |ui| -> Result<(), anyhow::Error> {
       // ui.heading("kicad-wakatime");
-      ui.label(format!("status: {status}"));
+      if let Some(ref last_crash) = self.last_crash {
+        ui.colored_label(Color32::RED, "kicad-wakatime crashed last time!");
+        ui.collapsing("details", |ui| {
+          egui::ScrollArea::vertical().max_height(150.0).show(ui, |ui| {
+            ui.monospace(last_crash);
+          });
+        });
+        ui.label("Please report it, with the diagnostics (which include the details).");
+        ui.horizontal(|ui| -> Result<(), anyhow::Error> {
+          if ui.button("export diagnostics").clicked() {
+            self.export_diagnostics()?;
+          }
+          if ui.button("dismiss").clicked() {
+            self.dismiss_crash()?;
+          }
+          Ok(())
+        }).inner?;
+        ui.separator();
+      }
+      if !self.kicad_settings_problems.is_empty() {
+        ui.colored_label(Color32::YELLOW, "KiCAD's backup settings need to be changed:");
+        for problem in &self.kicad_settings_problems {
+          ui.colored_label(Color32::YELLOW, format!("- {problem}"));
+        }
+        ui.label("Close KiCAD first, or it will overwrite the settings when it exits.");
+        if ui.button("apply recommended settings").clicked() {
+          self.apply_recommended_kicad_settings()?;
+        }
+        ui.separator();
+      }
+      if !self.config.auto_track {
+        let mut tracked = None;
+        for suggested_project in &self.suggested_projects {
+          let name = suggested_project.file_stem().unwrap_or_default().to_string_lossy();
+          ui.horizontal(|ui| {
+            ui.colored_label(Color32::YELLOW, format!("KiCAD has {name} open, but it is not tracked."));
+            if ui.button("track it").clicked() {
+              tracked = Some(suggested_project.clone());
+            }
+          });
+        }
+        if let Some(projects_file) = tracked {
+          self.track_suggested_project(projects_file)?;
+        }
+      }
+      if let Some(pause) = self.pause {
+        let text = match pause.until {
+          Some(until) => format!("Paused until {}, no heartbeats are sent", until.format("%H:%M")),
+          None => String::from("Paused, no heartbeats are sent"),
+        };
+        ui.horizontal(|ui| -> Result<(), anyhow::Error> {
+          ui.label(RichText::new(text).color(Color32::YELLOW).size(16.0).strong());
+          if ui.button("resume").clicked() {
+            self.resume()?;
+          }
+          Ok(())
+        }).inner?;
+      } else if self.in_quiet_hours() {
+        ui.label(RichText::new("Quiet hours, no heartbeats are sent").color(Color32::YELLOW).size(16.0).strong());
+      }
+      ui.horizontal(|ui| -> Result<(), anyhow::Error> {
+        ui.label(format!("status: {status}"));
+        if self.pause.is_none() {
+          let mut length = None;
+          ui.menu_button("pause", |ui| {
+            for (label, minutes) in PAUSE_LENGTHS {
+              if ui.button(*label).clicked() {
+                length = Some(*minutes);
+                ui.close_menu();
+              }
+            }
+          });
+          if let Some(length) = length {
+            self.pause(length)?;
+          }
+        }
+        Ok(())
+      }).inner?;
       ui.label(format!("last heartbeat: {last_heartbeat_label_text}"));
       if ui.button("settings").clicked() {
+        self.config_draft = self.config.clone();
+        self.quiet_hours_draft.clear();
+        self.connection_test = None;
         modal.open();
       }
+      if ui.button("export diagnostics").clicked() {
+        self.export_diagnostics()?;
+      }
       ui.add_space(20.0);
       ui.separator();
       egui_logger::logger_ui()
         .warn_color(Color32::YELLOW)
         .error_color(Color32::RED)
         .show(ui);
-    });
+      Ok(())
+    }).inner?;
     Ok(())
   }
 }
+
+/// A single line text field for a setting that is unset when empty.
+fn text_edit_optional(ui: &mut egui::Ui, value: &mut Option<String>) {
+  let mut text = value.clone().unwrap_or_default();
+  if ui.text_edit_singleline(&mut text).changed() {
+    *value = Some(text).filter(|text| !text.is_empty());
+  }
+}